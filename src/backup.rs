@@ -0,0 +1,247 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::file::get_vole_dir;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use std::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file, rename};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Name of the directory inside VoLe directory with backups.
+const BACKUPS_DIR_NAME: &str = "backups";
+/// Files of all storage backends and notes copied to each backup.
+const FILE_NAMES: [&str; 6] = [
+    "cards.txt",
+    "notes.txt",
+    "schedule.txt",
+    "reviews.txt",
+    "progress.txt",
+    "vole.sqlite",
+];
+/// Format of backup names, i.e. UTC time of their creation.
+const NAME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Backup of a single command. A command backs up the state before its
+/// first change only, not the half-updated files between its rewrites.
+#[derive(Debug, Default)]
+pub struct Backup {
+    created: bool,
+}
+
+impl Backup {
+    pub fn new() -> Backup {
+        Backup::default()
+    }
+
+    /// Copies cards, notes, schedule and progress to a new backup named by
+    /// the current time, e.g. before they are rewritten, unless this has
+    /// already been done. Backups over the count or age configured in
+    /// `config.txt` are removed afterwards.
+    pub fn create(&mut self) -> Result<(), Error> {
+        if self.created {
+            return Ok(());
+        }
+        let config = Config::load()?;
+        if config.backup_count() == 0 {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        snapshot(now)?;
+        self.created = true;
+        rotate(now, &config)
+    }
+}
+
+/// Returns names of all backups, the newest first.
+pub fn list() -> Result<Vec<String>, Error> {
+    let backups_dir = get_backups_dir()?;
+    let entries =
+        read_dir(&backups_dir).map_err(|error| Error::io("read directory", &backups_dir, error))?;
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|error| Error::io("read directory", &backups_dir, error))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if parse_name(&name).is_some() {
+            names.push(name);
+        }
+    }
+    names.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(names)
+}
+
+/// Replaces cards, notes, schedule and progress with their copies from
+/// backup `name`; files missing in the backup are removed. The current state
+/// is backed up first so that the restore can be undone.
+pub fn restore(name: &str) -> Result<(), Error> {
+    let backup_dir = get_backups_dir()?.join(name);
+    if parse_name(name).is_none() || !backup_dir.is_dir() {
+        return Err(Error::state(format!("There is no backup {}.", name)));
+    }
+
+    let config = Config::load()?;
+    let now = Utc::now();
+    if config.backup_count() > 0 {
+        snapshot(now)?;
+    }
+
+    let vole_dir = get_vole_dir()?;
+    for file_name in &FILE_NAMES {
+        let source = backup_dir.join(file_name);
+        let target = vole_dir.join(file_name);
+        if source.exists() {
+            let tmp_path = target.with_extension("restore.tmp");
+            copy(&source, &tmp_path).map_err(|error| Error::io("copy file", &source, error))?;
+            rename(&tmp_path, &target)
+                .map_err(|error| Error::io("rename file", &tmp_path, error))?;
+        } else {
+            match remove_file(&target) {
+                Ok(()) => (),
+                Err(ref error) if error.kind() == ErrorKind::NotFound => (),
+                Err(error) => return Err(Error::io("remove file", &target, error)),
+            }
+        }
+    }
+
+    if config.backup_count() > 0 {
+        rotate(now, &config)?;
+    }
+    Ok(())
+}
+
+/// Copies storage files to a backup named by `now` unless it exists.
+fn snapshot(now: DateTime<Utc>) -> Result<(), Error> {
+    let name = now.format(NAME_FORMAT).to_string();
+    let backups_dir = get_backups_dir()?;
+    let backup_dir = backups_dir.join(&name);
+    if backup_dir.exists() {
+        return Ok(());
+    }
+
+    // The backup is copied aside first so that it is never half-full.
+    let tmp_dir = backups_dir.join(format!("{}.tmp", name));
+    copy_files(&get_vole_dir()?, &tmp_dir)?;
+    rename(&tmp_dir, &backup_dir).map_err(|error| Error::io("rename directory", &tmp_dir, error))
+}
+
+/// Removes backups over the count or age configured in `config`.
+fn rotate(now: DateTime<Utc>, config: &Config) -> Result<(), Error> {
+    let backups_dir = get_backups_dir()?;
+    let names = list()?;
+    for name in expired(&names, now, config.backup_count(), config.backup_max_age()) {
+        let path = backups_dir.join(name);
+        remove_dir_all(&path).map_err(|error| Error::io("remove directory", &path, error))?;
+    }
+    Ok(())
+}
+
+/// Copies all existing storage files from `source` to directory `target`.
+fn copy_files(source: &Path, target: &Path) -> Result<(), Error> {
+    if target.exists() {
+        remove_dir_all(target).map_err(|error| Error::io("remove directory", target, error))?;
+    }
+    create_dir_all(target).map_err(|error| Error::io("create directory", target, error))?;
+
+    for file_name in &FILE_NAMES {
+        let path = source.join(file_name);
+        if path.exists() {
+            copy(&path, target.join(file_name))
+                .map_err(|error| Error::io("copy file", &path, error))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns backups of `names` (sorted the newest first) which are beyond
+/// the `count` newest ones or older than `max_age` at `now`. The newest
+/// backup is never expired.
+fn expired(names: &[String], now: DateTime<Utc>, count: usize, max_age: Duration) -> Vec<&str> {
+    names
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|&(i, name)| {
+            i >= count || parse_name(name).is_some_and(|created| now - created > max_age)
+        })
+        .map(|(_, name)| name.as_str())
+        .collect()
+}
+
+/// Parse time of creation of a backup from its name.
+fn parse_name(name: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(name, NAME_FORMAT)
+        .ok()
+        .map(|time| DateTime::from_utc(time, Utc))
+}
+
+/// Returns path to backups directory and creates it if necessary.
+fn get_backups_dir() -> Result<PathBuf, Error> {
+    let path = get_vole_dir()?.join(BACKUPS_DIR_NAME);
+    create_dir_all(&path).map_err(|error| Error::io("create directory", &path, error))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(
+            parse_name("20190310T080000.250Z"),
+            Some(Utc.ymd(2019, 3, 10).and_hms_milli(8, 0, 0, 250))
+        );
+        assert_eq!(parse_name("20190310T080000.000Z.tmp"), None);
+        assert_eq!(parse_name("latest"), None);
+    }
+
+    #[test]
+    fn test_expired() {
+        let names: Vec<String> = [
+            "20190312T080000.000Z",
+            "20190311T080000.000Z",
+            "20190310T080000.000Z",
+            "20190201T080000.000Z",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        let now = Utc.ymd(2019, 3, 12).and_hms(12, 0, 0);
+
+        assert_eq!(
+            expired(&names, now, 10, Duration::days(30)),
+            vec!["20190201T080000.000Z"]
+        );
+        assert_eq!(
+            expired(&names, now, 2, Duration::days(30)),
+            vec!["20190310T080000.000Z", "20190201T080000.000Z"]
+        );
+        assert_eq!(
+            expired(&names, now, 1, Duration::hours(1)),
+            vec![
+                "20190311T080000.000Z",
+                "20190310T080000.000Z",
+                "20190201T080000.000Z"
+            ]
+        );
+        assert!(expired(&names[..1], now, 1, Duration::zero()).is_empty());
+    }
+}
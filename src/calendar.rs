@@ -0,0 +1,191 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error;
+use chrono::{prelude::*, Duration, FixedOffset, NaiveDate};
+
+/// Time zone in which learning days are counted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timezone {
+    /// Time zone of the operating system.
+    Local,
+    /// A time zone with a fixed offset from UTC.
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Parse time zone from `local`, `UTC` or an offset in `+HH:MM` (or
+    /// `-HH:MM`) format.
+    pub fn parse(source: &str) -> Result<Timezone, Error> {
+        if source.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+        if source.eq_ignore_ascii_case("utc") {
+            return Ok(Timezone::Fixed(FixedOffset::east(0)));
+        }
+
+        let invalid = || Error::parse(format!("Invalid time zone: {}", source));
+
+        let sign = match source.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Err(invalid()),
+        };
+        // Only digits are accepted, a sign inside the offset is invalid.
+        let number = |part: &str| -> Result<u32, Error> {
+            if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            part.parse().map_err(|_| invalid())
+        };
+        let mut parts = source[1..].splitn(2, ':');
+        let hours = number(parts.next().unwrap())?;
+        let minutes = match parts.next() {
+            Some(minutes) => number(minutes)?,
+            None => 0,
+        };
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+
+        let seconds = sign * (hours * 3600 + minutes * 60) as i32;
+        FixedOffset::east_opt(seconds)
+            .map(Timezone::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Calendar maps instants to learning days. A learning day starts at a
+/// configurable hour (rather than at midnight) in a configurable time zone, so
+/// late night sessions count towards the previous day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calendar {
+    day_start: u32,
+    timezone: Timezone,
+}
+
+impl Default for Calendar {
+    fn default() -> Calendar {
+        Calendar {
+            day_start: 4,
+            timezone: Timezone::Local,
+        }
+    }
+}
+
+impl Calendar {
+    /// # Panics
+    ///
+    /// If `day_start` is not a valid hour (0 - 23).
+    pub fn new(day_start: u32, timezone: Timezone) -> Calendar {
+        if day_start > 23 {
+            panic!("Invalid day start hour: {}.", day_start);
+        }
+
+        Calendar {
+            day_start,
+            timezone,
+        }
+    }
+
+    /// Hour of the day at which learning days start.
+    pub fn day_start(&self) -> u32 {
+        self.day_start
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
+
+    /// Returns date and time of instant `instant` in calendar's time zone.
+    pub fn local(&self, instant: DateTime<Utc>) -> NaiveDateTime {
+        match self.timezone {
+            Timezone::Local => instant.with_timezone(&Local).naive_local(),
+            Timezone::Fixed(offset) => instant.with_timezone(&offset).naive_local(),
+        }
+    }
+
+    /// Returns learning day containing instant `instant`.
+    pub fn date(&self, instant: DateTime<Utc>) -> NaiveDate {
+        (self.local(instant) - Duration::hours(i64::from(self.day_start))).date()
+    }
+
+    /// Returns instant at which learning day `date` starts.
+    pub fn start(&self, date: NaiveDate) -> DateTime<Utc> {
+        let local = date.and_hms(self.day_start, 0, 0);
+        match self.timezone {
+            Timezone::Local => match Local.from_local_datetime(&local).earliest() {
+                Some(instant) => instant.with_timezone(&Utc),
+                // Day starts in a gap caused by a daylight saving time
+                // transition; gaps never last longer than an hour.
+                None => Local
+                    .from_local_datetime(&(local + Duration::hours(1)))
+                    .earliest()
+                    .unwrap()
+                    .with_timezone(&Utc),
+            },
+            Timezone::Fixed(offset) => offset
+                .from_local_datetime(&local)
+                .unwrap()
+                .with_timezone(&Utc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(Timezone::parse("local").unwrap(), Timezone::Local);
+        assert_eq!(
+            Timezone::parse("UTC").unwrap(),
+            Timezone::Fixed(FixedOffset::east(0))
+        );
+        assert_eq!(
+            Timezone::parse("+02:30").unwrap(),
+            Timezone::Fixed(FixedOffset::east(9000))
+        );
+        assert_eq!(
+            Timezone::parse("-05").unwrap(),
+            Timezone::Fixed(FixedOffset::west(18000))
+        );
+        assert_eq!(
+            Timezone::parse("Europe/Prague").unwrap_err().to_string(),
+            "Invalid time zone: Europe/Prague"
+        );
+        assert!(Timezone::parse("+24:00").is_err());
+        assert!(Timezone::parse("+-30").is_err());
+        assert!(Timezone::parse("+05:-30").is_err());
+        assert!(Timezone::parse("++05").is_err());
+        assert!(Timezone::parse("+").is_err());
+    }
+
+    #[test]
+    fn test_date() {
+        let calendar = Calendar::new(4, Timezone::parse("+02:00").unwrap());
+
+        let instant = Utc.ymd(2019, 3, 10).and_hms(22, 30, 0);
+        assert_eq!(calendar.date(instant), NaiveDate::from_ymd(2019, 3, 10));
+        let instant = Utc.ymd(2019, 3, 11).and_hms(1, 59, 0);
+        assert_eq!(calendar.date(instant), NaiveDate::from_ymd(2019, 3, 10));
+        let instant = Utc.ymd(2019, 3, 11).and_hms(2, 0, 0);
+        assert_eq!(calendar.date(instant), NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(calendar.start(NaiveDate::from_ymd(2019, 3, 11)), instant);
+    }
+}
@@ -15,18 +15,33 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::error::Error;
+use crate::media::Media;
+use chrono::Utc;
+use rand::{thread_rng, Rng};
+use std::cmp;
+
+/// Number of lower bits of a generated ID filled with random bits.
+const RANDOM_ID_BITS: u32 = 20;
+
 /// The core struct of VoLe representing a unit of learning or a so-called
 /// flash-card.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Card {
     id: u64,
     question: String,
     answer: String,
+    alternatives: Vec<String>,
+    hint: Option<String>,
+    media: Vec<Media>,
+    siblings: Vec<u64>,
+    note: Option<(u64, usize)>,
 }
 
 impl Card {
     /// Parse ID of a `Card` from a HEX string.
-    pub fn parse_id(id: &str) -> Result<u64, String> {
-        u64::from_str_radix(id, 16).map_err(|r| format!("Failed to parse card ID: {}", r))
+    pub fn parse_id(id: &str) -> Result<u64, Error> {
+        u64::from_str_radix(id, 16).map_err(|r| Error::field_source("card ID", r))
     }
 
     /// Serialize ID of a `Card` to a HEX string.
@@ -39,6 +54,11 @@ impl Card {
             id,
             question,
             answer,
+            alternatives: Vec::new(),
+            hint: None,
+            media: Vec::new(),
+            siblings: Vec::new(),
+            note: None,
         }
     }
 
@@ -53,6 +73,124 @@ impl Card {
     pub fn answer(&self) -> &str {
         &self.answer
     }
+
+    /// Accepted answers other than the main answer, e.g. synonyms of a
+    /// translated word.
+    pub fn alternatives(&self) -> &[String] {
+        &self.alternatives
+    }
+
+    /// Adds another accepted answer unless it is accepted already.
+    ///
+    /// # Errors
+    ///
+    /// The alternative is empty or consists of white space only, it would
+    /// accept an empty typed answer.
+    pub fn add_alternative(&mut self, alternative: String) -> Result<(), Error> {
+        if alternative.trim().is_empty() {
+            return Err(Error::parse("Alternative answer can't be empty."));
+        }
+        if alternative != self.answer && !self.alternatives.contains(&alternative) {
+            self.alternatives.push(alternative);
+        }
+        Ok(())
+    }
+
+    /// All accepted answers starting with the main answer.
+    pub fn answers(&self) -> Vec<&str> {
+        let mut answers = vec![self.answer.as_str()];
+        answers.extend(self.alternatives.iter().map(String::as_str));
+        answers
+    }
+
+    /// Returns whether `typed` matches any of the accepted answers. Letter
+    /// case and white space differences are ignored.
+    pub fn is_correct(&self, typed: &str) -> bool {
+        let typed = normalize(typed);
+        self.answers()
+            .iter()
+            .any(|&answer| normalize(answer) == typed)
+    }
+
+    /// Optional hint shown together with the question.
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    pub fn set_hint(&mut self, hint: String) {
+        self.hint = Some(hint);
+    }
+
+    /// Audio and image files attached to the card.
+    pub fn media(&self) -> &[Media] {
+        &self.media
+    }
+
+    pub fn add_media(&mut self, media: Media) {
+        self.media.push(media);
+    }
+
+    pub fn set_question(&mut self, question: String) {
+        self.question = question;
+    }
+
+    pub fn set_answer(&mut self, answer: String) {
+        self.answer = answer;
+    }
+
+    /// ID of the note the card was generated from and index of the template
+    /// of the note type which generated it. `None` for cards added directly.
+    pub fn note(&self) -> Option<(u64, usize)> {
+        self.note
+    }
+
+    pub fn set_note(&mut self, note: u64, template: usize) {
+        self.note = Some((note, template));
+    }
+
+    /// IDs of cards created together with this card, e.g. the reversed card
+    /// of a bidirectional pair. Cards generated from the same note are
+    /// siblings too but they are linked by the note instead.
+    pub fn siblings(&self) -> &[u64] {
+        &self.siblings
+    }
+
+    pub fn add_sibling(&mut self, id: u64) {
+        if !self.siblings.contains(&id) {
+            self.siblings.push(id);
+        }
+    }
+}
+
+/// Generator of card and note IDs which don't collide with IDs generated on
+/// other devices. Upper bits of an ID hold milliseconds since Unix epoch and
+/// lower bits are random, thus generated IDs are far greater than sequential
+/// IDs of older versions of VoLe. IDs from a single generator are strictly
+/// increasing.
+#[derive(Default)]
+pub struct IdGenerator {
+    last: u64,
+}
+
+impl IdGenerator {
+    pub fn new() -> IdGenerator {
+        IdGenerator::default()
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        let millis = Utc::now().timestamp_millis() as u64;
+        let random = thread_rng().gen::<u64>() >> (64 - RANDOM_ID_BITS);
+        self.last = cmp::max((millis << RANDOM_ID_BITS) | random, self.last + 1);
+        self.last
+    }
+}
+
+fn normalize(answer: &str) -> String {
+    answer
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
 }
 
 #[cfg(test)]
@@ -70,8 +208,8 @@ mod test {
             12682136550675316737
         ];
         assert_eq![
-            Card::parse_id("xxx").err().unwrap(),
-            String::from("Failed to parse card ID: invalid digit found in string")
+            Card::parse_id("xxx").unwrap_err().to_string(),
+            "Failed to parse card ID: invalid digit found in string"
         ];
     }
 
@@ -80,11 +218,46 @@ mod test {
         assert_eq![Card::serialize_id(49154), String::from("000000000000c002")];
     }
 
+    #[test]
+    fn test_id_generator() {
+        let mut generator = IdGenerator::new();
+        let first = generator.next_id();
+        let second = generator.next_id();
+        assert!(first > 1 << 60);
+        assert!(second > first);
+        assert_eq!(Card::serialize_id(second).len(), 16);
+    }
+
     #[test]
     fn test_card() {
         let card = Card::new(123, String::from("What?"), String::from("Something!"));
         assert_eq!(card.id(), 123);
         assert_eq!(card.question(), "What?");
         assert_eq!(card.answer(), "Something!");
+        assert!(card.siblings().is_empty());
+
+        let mut card = card;
+        card.add_sibling(124);
+        card.add_sibling(124);
+        assert_eq!(card.siblings(), &[124]);
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut card = Card::new(1, String::from("Hund"), String::from("dog"));
+        card.add_alternative(String::from("hound")).unwrap();
+        card.add_alternative(String::from("dog")).unwrap();
+        card.add_alternative(String::from("hound")).unwrap();
+        assert!(card.add_alternative(String::from(" \t")).is_err());
+        assert_eq!(card.alternatives(), &["hound"]);
+        assert_eq!(card.answers(), vec!["dog", "hound"]);
+
+        assert!(card.is_correct("dog"));
+        assert!(card.is_correct(" Hound\n"));
+        assert!(!card.is_correct("cat"));
+        assert!(!card.is_correct(""));
+
+        let card = Card::new(2, String::from("Katze"), String::from("a  cat"));
+        assert!(card.is_correct("A cat"));
     }
 }
@@ -0,0 +1,310 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::backup::Backup;
+use crate::calendar::Calendar;
+use crate::card::{Card, IdGenerator};
+use crate::error::Error;
+use crate::file::{
+    get_cards_file_path, get_schedule_file_path, rewrite_lines, CARDS_FORMAT, SCHEDULE_FORMAT,
+};
+use crate::scheduler::{ScheduleItem, MIN_EF};
+use crate::storage::Backend;
+use chrono::{DateTime, SecondsFormat, Utc};
+use fnv::FnvHashMap;
+use std::fmt;
+use std::fs::read_to_string;
+
+/// A problem found in cards or schedule file.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    file: &'static str,
+    line: usize,
+    description: String,
+    fixable: bool,
+}
+
+impl Problem {
+    /// Returns true if the problem is repaired by `Report::fix`.
+    pub fn is_fixable(&self) -> bool {
+        self.fixable
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.description)?;
+        if !self.fixable {
+            write!(f, " (can't be fixed automatically)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Problems found in cards and schedule files together with lines (ending
+/// with line-feed) of the files with all fixable problems repaired.
+#[derive(Debug, Default)]
+pub struct Report {
+    problems: Vec<Problem>,
+    cards: Vec<String>,
+    schedule: Vec<String>,
+}
+
+impl Report {
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Rewrites cards and schedule files with the repaired content. Malformed
+    /// lines are kept as they are. A backup is made first.
+    pub fn fix(&self) -> Result<(), Error> {
+        Backup::new().create()?;
+        rewrite_lines(&get_cards_file_path()?, &self.cards)?;
+        rewrite_lines(&get_schedule_file_path()?, &self.schedule)
+    }
+
+    fn report(&mut self, file: &'static str, line: usize, description: String, fixable: bool) {
+        self.problems.push(Problem {
+            file,
+            line,
+            description,
+            fixable,
+        });
+    }
+}
+
+/// Checks cards and schedule files of text storage. All problems are
+/// reported, not only the first one. Last revisits after `now` are reported
+/// as revisits in the future.
+pub fn check(calendar: &Calendar, now: DateTime<Utc>) -> Result<Report, Error> {
+    if Backend::current()? != Backend::Text {
+        return Err(Error::state(
+            "Only text storage can be checked, SQLite storage keeps IDs unique itself.",
+        ));
+    }
+
+    let cards_path = get_cards_file_path()?;
+    let cards =
+        read_to_string(&cards_path).map_err(|error| Error::io("read file", &cards_path, error))?;
+    let schedule_path = get_schedule_file_path()?;
+    let schedule = read_to_string(&schedule_path)
+        .map_err(|error| Error::io("read file", &schedule_path, error))?;
+
+    check_content(&cards, &schedule, calendar, now, &mut IdGenerator::new())
+}
+
+/// Checks content of cards and schedule files of any supported format
+/// version. Cards with duplicate IDs get new IDs from `ids` unless they are
+/// exact duplicates. Repaired files are of the current format version.
+fn check_content(
+    cards: &str,
+    schedule: &str,
+    calendar: &Calendar,
+    now: DateTime<Utc>,
+    ids: &mut IdGenerator,
+) -> Result<Report, Error> {
+    let mut report = Report::default();
+    report.cards.push(CARDS_FORMAT.header());
+    report.schedule.push(SCHEDULE_FORMAT.header());
+
+    // Line numbers and lines of the first card with each ID.
+    let mut card_lines: FnvHashMap<u64, (usize, &str)> = FnvHashMap::default();
+    for (number, line) in CARDS_FORMAT.records(cards)? {
+        let card = match Card::from_line(line) {
+            Ok(card) => card,
+            Err(error) => {
+                // The card may still be scheduled, its schedule isn't orphaned.
+                if let Some(Ok(id)) = line.split('\t').next().map(Card::parse_id) {
+                    card_lines.entry(id).or_insert((number, line));
+                }
+                report.report("cards.txt", number, error.to_string(), false);
+                report.cards.push(format!("{}\n", line));
+                continue;
+            }
+        };
+
+        let (first, first_line) = match card_lines.get(&card.id()) {
+            Some(&first) => first,
+            None => {
+                card_lines.insert(card.id(), (number, line));
+                report.cards.push(format!("{}\n", line));
+                continue;
+            }
+        };
+
+        if first_line == line {
+            let description = format!("Duplicate of the card on line {}.", first);
+            report.report("cards.txt", number, description, true);
+        } else {
+            let description = format!(
+                "Card ID {} is already used on line {}.",
+                Card::serialize_id(card.id()),
+                first
+            );
+            report.report("cards.txt", number, description, true);
+            let rest = line.split_once('\t').map_or("", |(_, rest)| rest);
+            let id = Card::serialize_id(ids.next_id());
+            report.cards.push(format!("{}\t{}\n", id, rest));
+        }
+    }
+
+    // Line numbers, indices to fixed lines and items of scheduled cards.
+    let mut items: FnvHashMap<u64, (usize, usize, ScheduleItem)> = FnvHashMap::default();
+    for (number, line) in SCHEDULE_FORMAT.records(schedule)? {
+        let (id, mut item) = match ScheduleItem::deserialize(line, calendar) {
+            Ok(item) => item,
+            Err(error) => {
+                report.report("schedule.txt", number, error.to_string(), false);
+                report.schedule.push(format!("{}\n", line));
+                continue;
+            }
+        };
+
+        if !card_lines.contains_key(&id) {
+            let description = format!("Schedule of missing card {}.", Card::serialize_id(id));
+            report.report("schedule.txt", number, description, true);
+            continue;
+        }
+
+        let mut changed = false;
+        if item.last_revisit() > now {
+            let description = format!(
+                "Last revisit is in the future: {}",
+                item.last_revisit()
+                    .to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+            report.report("schedule.txt", number, description, true);
+            item.clamp_last_revisit(now);
+            changed = true;
+        }
+        if !(item.ef() >= MIN_EF && item.ef().is_finite()) {
+            let description = format!("EF {} is out of range.", item.ef());
+            report.report("schedule.txt", number, description, true);
+            item.clamp_ef();
+            changed = true;
+        }
+
+        let line = if changed {
+            item.serialize(id)
+        } else {
+            format!("{}\n", line)
+        };
+
+        match items.get_mut(&id) {
+            Some((first, index, first_item)) => {
+                let description = format!(
+                    "Card {} is already scheduled on line {}.",
+                    Card::serialize_id(id),
+                    first
+                );
+                report.report("schedule.txt", number, description, true);
+                // Keep the last review, the same as sync does.
+                if item.last_revisit() > first_item.last_revisit() {
+                    report.schedule[*index] = line;
+                    *first_item = item;
+                }
+            }
+            None => {
+                items.insert(id, (number, report.schedule.len(), item));
+                report.schedule.push(line);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calendar::Timezone;
+    use chrono::TimeZone;
+
+    fn calendar() -> Calendar {
+        Calendar::new(4, Timezone::parse("UTC").unwrap())
+    }
+
+    #[test]
+    fn test_check_content() {
+        let cards = "\
+0000000000000001\tHund\tdog
+0000000000000002\tKatze\tcat
+0000000000000001\tHund\tdog
+0000000000000002\tMaus\tmouse
+0000000000000003\tVogel
+xyz\tFisch\tfish
+";
+        let schedule = "\
+0000000000000001\t2019-03-12T04:00:00Z\t2019-03-10T08:00:00Z\t1\t2.5\treview
+0000000000000004\t2019-03-12T04:00:00Z\t2019-03-10T08:00:00Z\t1\t2.5\treview
+0000000000000002\t2019-03-12T04:00:00Z\t2019-03-11T08:00:00Z\t1\t0.5\treview
+0000000000000003\t2019-03-12T04:00:00Z\t2019-04-01T08:00:00Z\t1\t2.5\treview
+0000000000000001\t2019-03-13T04:00:00Z\t2019-03-11T08:00:00Z\t2\t2.6\treview
+0000000000000002\tsoon
+";
+        let now = Utc.ymd(2019, 3, 12).and_hms(12, 0, 0);
+        let report =
+            check_content(cards, schedule, &calendar(), now, &mut IdGenerator::new()).unwrap();
+
+        let problems: Vec<String> = report
+            .problems()
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect();
+        assert_eq!(problems.len(), 9);
+        assert_eq!(problems[0], "cards.txt:3: Duplicate of the card on line 1.");
+        assert_eq!(
+            problems[1],
+            "cards.txt:4: Card ID 0000000000000002 is already used on line 2."
+        );
+        assert!(problems[2].starts_with("cards.txt:5: Expected at least three"));
+        assert!(problems[2].ends_with(" (can't be fixed automatically)"));
+        assert!(problems[3].starts_with("cards.txt:6: "));
+        assert_eq!(
+            problems[4],
+            "schedule.txt:2: Schedule of missing card 0000000000000004."
+        );
+        assert_eq!(problems[5], "schedule.txt:3: EF 0.5 is out of range.");
+        assert_eq!(
+            problems[6],
+            "schedule.txt:4: Last revisit is in the future: 2019-04-01T08:00:00Z"
+        );
+        assert_eq!(
+            problems[7],
+            "schedule.txt:5: Card 0000000000000001 is already scheduled on line 1."
+        );
+        assert!(problems[8].starts_with("schedule.txt:6: "));
+
+        assert_eq!(report.cards.len(), 6);
+        assert_eq!(report.cards[0], "#vole-cards 2\n");
+        assert_eq!(report.cards[1], "0000000000000001\tHund\tdog\n");
+        assert!(report.cards[3].ends_with("\tMaus\tmouse\n"));
+        assert!(!report.cards[3].starts_with("0000000000000002"));
+        assert_eq!(report.cards[4], "0000000000000003\tVogel\n");
+
+        assert_eq!(
+            report.schedule,
+            vec![
+                "#vole-schedule 2\n",
+                "0000000000000001\t2019-03-13T04:00:00Z\t2019-03-11T08:00:00Z\t2\t2.6\treview\n",
+                "0000000000000002\t2019-03-12T04:00:00Z\t2019-03-11T08:00:00Z\t1\t1.3\treview\n",
+                "0000000000000003\t2019-03-12T12:00:00Z\t2019-03-12T12:00:00Z\t1\t2.5\treview\n",
+                "0000000000000002\tsoon\n",
+            ]
+        );
+    }
+}
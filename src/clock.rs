@@ -0,0 +1,92 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of current time. All time dependent computations (e.g. scheduling)
+/// obtain current time from a `Clock` so they can be tested or simulated
+/// independently of the system clock.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock reading system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock stopped at a given instant.
+pub struct FixedClock {
+    instant: DateTime<Utc>,
+}
+
+impl FixedClock {
+    pub fn new(instant: DateTime<Utc>) -> FixedClock {
+        FixedClock { instant }
+    }
+
+    /// Move the clock to a new instant.
+    pub fn set(&mut self, instant: DateTime<Utc>) {
+        self.instant = instant;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.instant
+    }
+}
+
+/// Clock shifted by a constant offset from another clock.
+pub struct OffsetClock<C: Clock> {
+    clock: C,
+    offset: Duration,
+}
+
+impl<C: Clock> OffsetClock<C> {
+    pub fn new(clock: C, offset: Duration) -> OffsetClock<C> {
+        OffsetClock { clock, offset }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now() + self.offset
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_clocks() {
+        let instant = Utc.ymd(2019, 3, 10).and_hms(12, 0, 0);
+        let mut clock = FixedClock::new(instant);
+        assert_eq!(clock.now(), instant);
+        clock.set(instant + Duration::days(1));
+        assert_eq!(clock.now(), Utc.ymd(2019, 3, 11).and_hms(12, 0, 0));
+
+        let clock = OffsetClock::new(FixedClock::new(instant), Duration::hours(-13));
+        assert_eq!(clock.now(), Utc.ymd(2019, 3, 9).and_hms(23, 0, 0));
+    }
+}
@@ -0,0 +1,444 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::calendar::{Calendar, Timezone};
+use crate::error::Error;
+use crate::file::get_vole_dir;
+use chrono::Duration;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+
+/// File name of the file storing deck configuration.
+const CONFIG_FILE_NAME: &str = "config.txt";
+
+/// Configuration of the deck stored in VoLe directory. It is loaded from a
+/// text file with one `key = value` pair per line. Empty lines and lines
+/// starting with `#` are ignored.
+#[derive(Clone)]
+pub struct Config {
+    new_per_day: usize,
+    reviews_per_day: usize,
+    calendar: Calendar,
+    learning_steps: Vec<Duration>,
+    relearning_steps: Vec<Duration>,
+    learn_ahead: Duration,
+    fuzz: f32,
+    load_balance: bool,
+    backlog_per_day: Option<usize>,
+    bury_siblings: bool,
+    type_answer: bool,
+    audio_command: Option<String>,
+    image_command: Option<String>,
+    speak_command: Option<String>,
+    question_language: Option<String>,
+    answer_language: Option<String>,
+    speak_automatically: bool,
+    backup_count: usize,
+    backup_max_age: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            new_per_day: 20,
+            reviews_per_day: 200,
+            calendar: Calendar::default(),
+            learning_steps: vec![Duration::minutes(1), Duration::minutes(10)],
+            relearning_steps: vec![Duration::minutes(10)],
+            learn_ahead: Duration::minutes(20),
+            fuzz: 0.05,
+            load_balance: false,
+            backlog_per_day: None,
+            bury_siblings: false,
+            type_answer: false,
+            audio_command: None,
+            image_command: None,
+            speak_command: None,
+            question_language: None,
+            answer_language: None,
+            speak_automatically: false,
+            backup_count: 20,
+            backup_max_age: Duration::days(30),
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from disk. Default configuration is returned if
+    /// the configuration file doesn't exist.
+    pub fn load() -> Result<Config, Error> {
+        let mut path = get_vole_dir()?;
+        path.push(CONFIG_FILE_NAME);
+
+        match read_to_string(&path) {
+            Ok(content) => Config::parse(&content),
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(Error::io("read file", &path, error)),
+        }
+    }
+
+    /// Parse configuration from the content of a configuration file. Options
+    /// missing in the content keep their default values.
+    pub fn parse(content: &str) -> Result<Config, Error> {
+        let mut config = Config::default();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => {
+                    let reason = format!("Expected \"key = value\", got: {}", line);
+                    return Err(Error::parse(reason).at_line(i + 1));
+                }
+            };
+
+            config
+                .set(key, value)
+                .map_err(|error| error.at_line(i + 1))?;
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "new_per_day" => self.new_per_day = parse_count(key, value)?,
+            "reviews_per_day" => self.reviews_per_day = parse_count(key, value)?,
+            "day_start" => {
+                let hour = parse_hour(value)?;
+                self.calendar = Calendar::new(hour, self.calendar.timezone());
+            }
+            "timezone" => {
+                let timezone = Timezone::parse(value)?;
+                self.calendar = Calendar::new(self.calendar.day_start(), timezone);
+            }
+            "learning_steps" => self.learning_steps = parse_steps(value)?,
+            "relearning_steps" => self.relearning_steps = parse_steps(value)?,
+            "learn_ahead" => self.learn_ahead = parse_duration_or_zero(value)?,
+            "fuzz" => {
+                self.fuzz = match value.parse() {
+                    Ok(fuzz) if (0.0..1.0).contains(&fuzz) => fuzz,
+                    _ => return Err(Error::parse(format!("Invalid fuzz: {}", value))),
+                }
+            }
+            "load_balance" => self.load_balance = parse_bool(key, value)?,
+            "bury_siblings" => self.bury_siblings = parse_bool(key, value)?,
+            "type_answer" => self.type_answer = parse_bool(key, value)?,
+            "audio_command" => self.audio_command = parse_optional(value),
+            "image_command" => self.image_command = parse_optional(value),
+            "speak_command" => self.speak_command = parse_optional(value),
+            "question_language" => self.question_language = parse_optional(value),
+            "answer_language" => self.answer_language = parse_optional(value),
+            "speak_automatically" => self.speak_automatically = parse_bool(key, value)?,
+            "backup_count" => self.backup_count = parse_count(key, value)?,
+            "backup_max_age" => self.backup_max_age = parse_duration(value)?,
+            "backlog_per_day" => {
+                self.backlog_per_day = match value {
+                    "unlimited" => None,
+                    _ => Some(parse_count(key, value)?),
+                }
+            }
+            _ => return Err(Error::parse(format!("Unknown option: {}", key))),
+        }
+
+        Ok(())
+    }
+
+    /// Maximum number of new cards automatically introduced per day.
+    pub fn new_per_day(&self) -> usize {
+        self.new_per_day
+    }
+
+    /// Maximum number of due cards reviewed per day.
+    pub fn reviews_per_day(&self) -> usize {
+        self.reviews_per_day
+    }
+
+    /// Calendar determining start of learning days.
+    pub fn calendar(&self) -> Calendar {
+        self.calendar
+    }
+
+    /// Delays after which a new card is repeated before it graduates to
+    /// review (i.e. it is scheduled on daily basis).
+    pub fn learning_steps(&self) -> &[Duration] {
+        &self.learning_steps
+    }
+
+    /// Delays after which a forgotten card is repeated before it returns to
+    /// review.
+    pub fn relearning_steps(&self) -> &[Duration] {
+        &self.relearning_steps
+    }
+
+    /// How much earlier can be cards in learning shown when there is nothing
+    /// else to learn.
+    pub fn learn_ahead(&self) -> Duration {
+        self.learn_ahead
+    }
+
+    /// Relative size of range around an interval (in days) computed by SM-2
+    /// from which the actual interval is randomly chosen. Zero disables
+    /// fuzzing.
+    pub fn fuzz(&self) -> f32 {
+        self.fuzz
+    }
+
+    /// Whether the least busy day in fuzz range is chosen instead of a
+    /// random one.
+    pub fn load_balance(&self) -> bool {
+        self.load_balance
+    }
+
+    /// Maximum number of reviews overdue from previous days scheduled per
+    /// day, `None` if unlimited.
+    pub fn backlog_per_day(&self) -> Option<usize> {
+        self.backlog_per_day
+    }
+
+    /// Whether siblings of a learned card (e.g. the reversed card of a
+    /// bidirectional pair) are postponed to the next day.
+    pub fn bury_siblings(&self) -> bool {
+        self.bury_siblings
+    }
+
+    /// Whether the answer is typed and compared with accepted answers before
+    /// it is shown.
+    pub fn type_answer(&self) -> bool {
+        self.type_answer
+    }
+
+    /// Command playing audio files attached to cards with `{file}`
+    /// placeholder for path of the file, e.g. `mpv {file}`.
+    pub fn audio_command(&self) -> Option<&str> {
+        self.audio_command.as_deref()
+    }
+
+    /// Command previewing image files attached to cards with `{file}`
+    /// placeholder for path of the file, e.g. `chafa {file}`.
+    pub fn image_command(&self) -> Option<&str> {
+        self.image_command.as_deref()
+    }
+
+    /// Text-to-speech command with `{text}` and `{lang}` placeholders, e.g.
+    /// `espeak-ng -v {lang} {text}`.
+    pub fn speak_command(&self) -> Option<&str> {
+        self.speak_command.as_deref()
+    }
+
+    /// Language code of questions, questions are not spoken if unset.
+    pub fn question_language(&self) -> Option<&str> {
+        self.question_language.as_deref()
+    }
+
+    /// Language code of answers, answers are not spoken if unset.
+    pub fn answer_language(&self) -> Option<&str> {
+        self.answer_language.as_deref()
+    }
+
+    /// Whether questions and answers are spoken as soon as they are shown
+    /// rather than on request.
+    pub fn speak_automatically(&self) -> bool {
+        self.speak_automatically
+    }
+
+    /// Maximum number of kept backups, zero disables backups.
+    pub fn backup_count(&self) -> usize {
+        self.backup_count
+    }
+
+    /// Age after which backups are removed.
+    pub fn backup_max_age(&self) -> Duration {
+        self.backup_max_age
+    }
+}
+
+fn parse_count(key: &str, value: &str) -> Result<usize, Error> {
+    value
+        .parse()
+        .map_err(|reason| Error::field_source(key, reason))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, Error> {
+    match value {
+        "true" | "yes" => Ok(true),
+        "false" | "no" => Ok(false),
+        _ => Err(Error::field(key, "expected true or false")),
+    }
+}
+
+/// Parse an optional text value (e.g. an external command), an empty value is
+/// unset.
+fn parse_optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Parse hour of a day given either as a plain number (e.g. `4`) or in
+/// `HH:MM` format with zero minutes (e.g. `04:00`).
+fn parse_hour(value: &str) -> Result<u32, Error> {
+    let invalid = || Error::parse(format!("Invalid day start hour: {}", value));
+
+    let hour = match value.find(':') {
+        Some(index) if &value[index..] == ":00" => &value[..index],
+        Some(_) => return Err(invalid()),
+        None => value,
+    };
+    match hour.parse() {
+        Ok(hour) if hour < 24 => Ok(hour),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a duration given as a number followed by a unit: `m` for minutes,
+/// `h` for hours or `d` for days (e.g. `10m`). The duration must be positive.
+fn parse_duration(value: &str) -> Result<Duration, Error> {
+    let invalid = || Error::parse(format!("Invalid duration: {}", value));
+
+    let (number, seconds) = if let Some(number) = value.strip_suffix('m') {
+        (number, 60)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 60 * 60)
+    } else if let Some(number) = value.strip_suffix('d') {
+        (number, 24 * 60 * 60)
+    } else {
+        return Err(invalid());
+    };
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+    if number <= 0 {
+        return Err(invalid());
+    }
+    number
+        .checked_mul(seconds)
+        .filter(|&seconds| seconds <= Duration::max_value().num_seconds())
+        .map(Duration::seconds)
+        .ok_or_else(invalid)
+}
+
+/// Parse a duration like `parse_duration` which may also be zero (e.g. `0m`).
+fn parse_duration_or_zero(value: &str) -> Result<Duration, Error> {
+    if value.strip_suffix(|c| "mhd".contains(c)) == Some("0") {
+        return Ok(Duration::zero());
+    }
+    parse_duration(value)
+}
+
+/// Parse a white space separated list of durations (e.g. `1m 10m 1h`).
+fn parse_steps(value: &str) -> Result<Vec<Duration>, Error> {
+    value.split_whitespace().map(parse_duration).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("# Limits\n\nnew_per_day = 5\n reviews_per_day=50 \n").unwrap();
+        assert_eq!(config.new_per_day(), 5);
+        assert_eq!(config.reviews_per_day(), 50);
+
+        let config = Config::parse("reviews_per_day = 10").unwrap();
+        assert_eq!(config.new_per_day(), 20);
+        assert_eq!(config.reviews_per_day(), 10);
+        assert_eq!(config.calendar(), Calendar::default());
+
+        let config = Config::parse("timezone = +01:00\nday_start = 03:00").unwrap();
+        assert_eq!(
+            config.calendar(),
+            Calendar::new(3, Timezone::parse("+01:00").unwrap())
+        );
+
+        let config = Config::parse("learning_steps = 1m 2h 1d\nrelearning_steps =").unwrap();
+        assert_eq!(
+            config.learning_steps(),
+            &[Duration::minutes(1), Duration::hours(2), Duration::days(1)]
+        );
+        assert!(config.relearning_steps().is_empty());
+        assert_eq!(config.learn_ahead(), Duration::minutes(20));
+        let config = Config::parse("learn_ahead = 0h").unwrap();
+        assert_eq!(config.learn_ahead(), Duration::zero());
+        assert!(!config.load_balance());
+
+        let config = Config::parse("fuzz = 0.1\nload_balance = yes").unwrap();
+        assert_eq!(config.fuzz(), 0.1);
+        assert!(config.load_balance());
+        assert_eq!(config.backlog_per_day(), None);
+
+        let config = Config::parse("backlog_per_day = 30").unwrap();
+        assert_eq!(config.backlog_per_day(), Some(30));
+        assert_eq!(config.audio_command(), None);
+
+        let config = Config::parse("audio_command = mpv --really-quiet {file}").unwrap();
+        assert_eq!(config.audio_command(), Some("mpv --really-quiet {file}"));
+        assert_eq!(config.backup_count(), 20);
+
+        let config = Config::parse("backup_count = 5\nbackup_max_age = 7d").unwrap();
+        assert_eq!(config.backup_count(), 5);
+        assert_eq!(config.backup_max_age(), Duration::days(7));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Config::parse("new_per_day = many")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Error on line 1: Failed to parse new_per_day: invalid digit found in string"
+        );
+        assert_eq!(
+            Config::parse("\nnew_per_day").err().unwrap().to_string(),
+            "Error on line 2: Expected \"key = value\", got: new_per_day"
+        );
+        assert_eq!(
+            Config::parse("color = blue").err().unwrap().to_string(),
+            "Error on line 1: Unknown option: color"
+        );
+        assert_eq!(
+            Config::parse("day_start = 4:30").err().unwrap().to_string(),
+            "Error on line 1: Invalid day start hour: 4:30"
+        );
+        assert_eq!(
+            Config::parse("learning_steps = 1m 10")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Error on line 1: Invalid duration: 10"
+        );
+        assert!(Config::parse("learn_ahead = 1é").is_err());
+        assert!(Config::parse("learn_ahead = 99999999999999d").is_err());
+        assert!(Config::parse("learning_steps = 1m 0m").is_err());
+        assert!(Config::parse("learn_ahead = -5m").is_err());
+        assert!(Config::parse("learn_ahead = m").is_err());
+        assert_eq!(
+            Config::parse("fuzz = 2.0").err().unwrap().to_string(),
+            "Error on line 1: Invalid fuzz: 2.0"
+        );
+        assert!(Config::parse("fuzz = -0.1").is_err());
+        assert!(Config::parse("fuzz = 1").is_err());
+    }
+}
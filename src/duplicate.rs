@@ -0,0 +1,220 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of cards with the same question. Questions are compared after
+//! normalization which ignores markup, letter case, white space and
+//! diacritics. Fuzzy matching additionally tolerates a few typos.
+
+use crate::card::Card;
+use crate::markup::{self, Style};
+use fnv::FnvHashMap;
+use std::{cmp, mem};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Normalized questions of fuzzy duplicates differ in at most one edit
+/// (inserted, removed or replaced character) per this many characters.
+const CHARS_PER_EDIT: usize = 5;
+
+/// Returns `text` without markup, diacritics and surrounding white space, in
+/// lower case and with all inner white space collapsed to single spaces.
+pub fn normalize(text: &str) -> String {
+    markup::render(text, Style::Plain)
+        .nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Returns true if two normalized texts are the same or if `fuzzy` is true
+/// and they differ only in a few characters.
+fn matches(first: &str, second: &str, fuzzy: bool) -> bool {
+    if first == second {
+        return true;
+    }
+    if !fuzzy {
+        return false;
+    }
+
+    let (first_length, second_length) = (first.chars().count(), second.chars().count());
+    let length = cmp::max(first_length, second_length);
+    // The edit distance is at least the difference of the lengths.
+    if (length - cmp::min(first_length, second_length)) * CHARS_PER_EDIT > length {
+        return false;
+    }
+    edit_distance(first, second) * CHARS_PER_EDIT <= length
+}
+
+/// Levenshtein distance of two strings in characters.
+fn edit_distance(first: &str, second: &str) -> usize {
+    let second: Vec<char> = second.chars().collect();
+    let mut previous: Vec<usize> = (0..=second.len()).collect();
+    let mut current = vec![0; second.len() + 1];
+
+    for (i, a) in first.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &b) in second.iter().enumerate() {
+            let replace = previous[j] + if a == b { 0 } else { 1 };
+            current[j + 1] = cmp::min(replace, cmp::min(previous[j + 1], current[j]) + 1);
+        }
+        mem::swap(&mut previous, &mut current);
+    }
+
+    previous[second.len()]
+}
+
+/// Returns cards of `cards` with the same question as `question`.
+pub fn find<'a>(cards: &'a [Card], question: &str, fuzzy: bool) -> Vec<&'a Card> {
+    let question = normalize(question);
+    cards
+        .iter()
+        .filter(|card| matches(&normalize(card.question()), &question, fuzzy))
+        .collect()
+}
+
+/// Returns groups of two or more cards of `cards` with the same question.
+/// With `fuzzy` matching, a card is grouped with all cards it matches
+/// directly or through other cards.
+pub fn groups(cards: &[Card], fuzzy: bool) -> Vec<Vec<&Card>> {
+    let questions: Vec<String> = cards
+        .iter()
+        .map(|card| normalize(card.question()))
+        .collect();
+
+    // Forest of groups given by parent of each card, the root of a group is
+    // its first card.
+    let mut parents: Vec<usize> = (0..cards.len()).collect();
+    let mut first: FnvHashMap<&str, usize> = FnvHashMap::default();
+    for (i, question) in questions.iter().enumerate() {
+        let j = *first.entry(question).or_insert(i);
+        union(&mut parents, i, j);
+    }
+
+    if fuzzy {
+        // Distinct questions ordered by length; a question is compared only
+        // to shorter questions whose length doesn't rule out a match.
+        let mut distinct: Vec<(usize, usize)> = first
+            .values()
+            .map(|&i| (questions[i].chars().count(), i))
+            .collect();
+        distinct.sort_unstable();
+        for (k, &(length, i)) in distinct.iter().enumerate() {
+            for &(other_length, j) in distinct[..k].iter().rev() {
+                if (length - other_length) * CHARS_PER_EDIT > length {
+                    break;
+                }
+                if edit_distance(&questions[i], &questions[j]) * CHARS_PER_EDIT <= length {
+                    union(&mut parents, i, j);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<&Card>> = vec![Vec::new(); cards.len()];
+    for (i, card) in cards.iter().enumerate() {
+        groups[root(&mut parents, i)].push(card);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Returns the root of the group of card `i` in forest `parents`.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Merges groups of cards `i` and `j` in forest `parents`, the root of the
+/// merged group is the first card of both groups.
+fn union(parents: &mut [usize], i: usize, j: usize) {
+    let (i, j) = (root(parents, i), root(parents, j));
+    parents[cmp::max(i, j)] = cmp::min(i, j);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(id: u64, question: &str) -> Card {
+        Card::new(id, question.to_string(), "answer".to_string())
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  **Der**   Hund "), "der hund");
+        assert_eq!(normalize("Äpfel  und Birnen"), "apfel und birnen");
+        assert_eq!(normalize("Příliš"), "prilis");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("hund", ""), 4);
+        assert_eq!(edit_distance("hund", "hunde"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_find() {
+        let cards = [
+            card(1, "der Hund"),
+            card(2, "die Katze"),
+            card(3, "der Hunde"),
+        ];
+        let ids = |found: Vec<&Card>| found.iter().map(|card| card.id()).collect::<Vec<u64>>();
+
+        assert_eq!(ids(find(&cards, "Der  HUND", false)), vec![1]);
+        assert_eq!(ids(find(&cards, "der Hund", true)), vec![1, 3]);
+        assert!(find(&cards, "die Maus", true).is_empty());
+    }
+
+    #[test]
+    fn test_groups() {
+        let cards = [
+            card(1, "der Hund"),
+            card(2, "die Katze"),
+            card(3, "Der Hund"),
+            card(4, "der Hunde"),
+            card(5, "die Maus"),
+        ];
+        let ids = |groups: Vec<Vec<&Card>>| {
+            groups
+                .iter()
+                .map(|group| group.iter().map(|card| card.id()).collect())
+                .collect::<Vec<Vec<u64>>>()
+        };
+
+        assert_eq!(ids(groups(&cards, false)), vec![vec![1, 3]]);
+        assert_eq!(ids(groups(&cards, true)), vec![vec![1, 3, 4]]);
+
+        // Cards are grouped through a card matching both of them.
+        let cards = [
+            card(1, "hund"),
+            card(2, "hundes"),
+            card(3, "katze"),
+            card(4, "hunde"),
+            card(5, "hundes"),
+        ];
+        assert_eq!(ids(groups(&cards, true)), vec![vec![1, 2, 4, 5]]);
+        assert_eq!(ids(groups(&cards, false)), vec![vec![2, 5]]);
+    }
+}
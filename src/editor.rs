@@ -0,0 +1,218 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Entering new cards in a text editor. Cards are written to a draft file
+//! which is kept until they are stored so that nothing is lost when some of
+//! them are invalid. Cards added one by one and in a batch have separate
+//! draft files, so a kept draft is always opened again in its mode.
+
+use crate::error::Error;
+use crate::file::get_vole_dir;
+use crate::hook;
+use std::env;
+use std::fs::{read_to_string, remove_file, write};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Name of the draft file of a single card inside VoLe directory.
+const DRAFT_FILE_NAME: &str = "add.txt";
+/// Name of the draft file of a batch of cards inside VoLe directory.
+const BATCH_DRAFT_FILE_NAME: &str = "add-batch.txt";
+/// Editor used if neither `VISUAL` nor `EDITOR` environment variable is set.
+const DEFAULT_EDITOR: &str = "vi";
+
+const TEMPLATE: &str = "
+
+# Write the question on the first line and the answer on the following lines.
+# More lines of the answer are joined with line breaks, there can't be an empty
+# line in between. Lines starting with # are ignored and an empty file adds no
+# card.
+";
+
+const BATCH_TEMPLATE: &str = "
+
+# Write the question on the first line of each card and the answer on the
+# following lines; cards are separated by an empty line. More lines of the
+# answer are joined with line breaks. Lines starting with # are ignored.
+";
+
+/// Opens the draft file (a template unless a previous draft was kept) in the
+/// user's editor and returns questions and answers of the cards written
+/// there. More than one card is accepted only if `batch` is true. All cards
+/// are validated, none is returned if any of them is invalid.
+pub fn edit_cards(batch: bool) -> Result<Vec<(String, String)>, Error> {
+    let path = get_draft_path(batch)?;
+    if !path.exists() {
+        let template = if batch { BATCH_TEMPLATE } else { TEMPLATE };
+        write(&path, template).map_err(|error| Error::io("write file", &path, error))?;
+    }
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from(DEFAULT_EDITOR));
+    hook::run_command(
+        &format!("{} {{file}}", editor),
+        &[("{file}", &path.to_string_lossy())],
+    )?;
+
+    let content = read_to_string(&path).map_err(|error| Error::io("read file", &path, error))?;
+    parse_cards(&content, batch).map_err(|error| keep(error, batch))
+}
+
+/// Extends `error` which failed storing of cards of the draft (of a batch if
+/// `batch` is true) with a note that the draft is kept.
+pub fn keep(error: Error, batch: bool) -> Error {
+    let (path, command) = match get_draft_path(batch) {
+        Ok(path) => (
+            path,
+            if batch {
+                "vole add --batch"
+            } else {
+                "vole add"
+            },
+        ),
+        Err(error) => return error,
+    };
+    Error::state(format!(
+        "{}\nThe cards are kept in {} and opened again by the next {}.",
+        error,
+        path.display(),
+        command
+    ))
+}
+
+/// Removes the draft file (of a batch if `batch` is true) once its cards
+/// have been stored.
+pub fn discard(batch: bool) -> Result<(), Error> {
+    let path = get_draft_path(batch)?;
+    match remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(Error::io("remove file", &path, error)),
+    }
+}
+
+/// Parses questions and answers from `content` of the draft file. Lines of
+/// an answer are joined with `\n` markup line breaks and cards are separated
+/// by empty lines. More than one card is an error unless `batch` is true.
+fn parse_cards(content: &str, batch: bool) -> Result<Vec<(String, String)>, Error> {
+    // Blocks of line numbers and lines, each block is a card.
+    let mut blocks: Vec<Vec<(usize, &str)>> = vec![Vec::new()];
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.contains('\t') {
+            return Err(Error::parse("Cards can't contain TAB characters.").at_line(number));
+        }
+
+        let line = line.trim_end();
+        if !line.is_empty() {
+            blocks.last_mut().unwrap().push((number, line));
+        } else {
+            blocks.push(Vec::new());
+        }
+    }
+    blocks.retain(|block| !block.is_empty());
+
+    if let (false, Some(block)) = (batch, blocks.get(1)) {
+        let reason = "Only one card can be added, use vole add --batch to add more.";
+        return Err(Error::parse(reason).at_line(block[0].0));
+    }
+
+    let mut cards = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let (number, question) = block[0];
+        let answer: Vec<&str> = block[1..].iter().map(|&(_, line)| line).collect();
+        let answer = answer.join("\n").trim().replace('\n', "\\n");
+        if answer.is_empty() {
+            return Err(Error::parse("The card has no answer.").at_line(number));
+        }
+        cards.push((question.trim().to_string(), answer));
+    }
+    Ok(cards)
+}
+
+/// Returns path to the draft file of new cards, of a batch if `batch` is
+/// true.
+fn get_draft_path(batch: bool) -> Result<PathBuf, Error> {
+    let name = if batch {
+        BATCH_DRAFT_FILE_NAME
+    } else {
+        DRAFT_FILE_NAME
+    };
+    Ok(get_vole_dir()?.join(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(question: &str, answer: &str) -> (String, String) {
+        (question.to_string(), answer.to_string())
+    }
+
+    #[test]
+    fn test_parse_cards() {
+        let content = "\
+# A comment.
+
+der Hund
+dog
+- *pl.* dogs
+
+die Katze
+cat
+";
+        assert_eq!(
+            parse_cards(content, true).unwrap(),
+            vec![
+                card("der Hund", "dog\\n- *pl.* dogs"),
+                card("die Katze", "cat")
+            ]
+        );
+        assert_eq!(
+            parse_cards(&content[..content.find("die").unwrap()], false).unwrap(),
+            vec![card("der Hund", "dog\\n- *pl.* dogs")]
+        );
+        assert!(parse_cards(TEMPLATE, false).unwrap().is_empty());
+        assert!(parse_cards(BATCH_TEMPLATE, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid_cards() {
+        assert_eq!(
+            parse_cards("der Hund\ndog\n\ndie Katze\n", true)
+                .unwrap_err()
+                .to_string(),
+            "Error on line 4: The card has no answer."
+        );
+        assert_eq!(
+            parse_cards("der\tHund\ndog\n", false)
+                .unwrap_err()
+                .to_string(),
+            "Error on line 1: Cards can't contain TAB characters."
+        );
+        assert_eq!(
+            parse_cards("der Hund\ndog\n\ndie Katze\ncat\n", false)
+                .unwrap_err()
+                .to_string(),
+            "Error on line 4: Only one card can be added, use vole add --batch to add more."
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error of any VoLe operation.
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation (e.g. `open file`) on `path` failed.
+    Io {
+        operation: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Communication with a remote `address` (e.g. a sync server) failed.
+    Network {
+        operation: &'static str,
+        address: String,
+        source: io::Error,
+    },
+    /// Content of a file or an input is malformed.
+    Parse {
+        /// One based number of the malformed line.
+        line: Option<usize>,
+        /// Name of the malformed field (e.g. `card ID`).
+        field: Option<String>,
+        reason: String,
+        source: Option<Box<dyn error::Error + Send + Sync>>,
+    },
+    /// Operation is not possible in the current state, e.g. a referenced note
+    /// doesn't exist.
+    State(String),
+    /// An operation of SQLite storage failed.
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl Error {
+    pub fn io(operation: &'static str, path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            operation,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn network(operation: &'static str, address: &str, source: io::Error) -> Error {
+        Error::Network {
+            operation,
+            address: address.to_string(),
+            source,
+        }
+    }
+
+    pub fn parse<S: Into<String>>(reason: S) -> Error {
+        Error::Parse {
+            line: None,
+            field: None,
+            reason: reason.into(),
+            source: None,
+        }
+    }
+
+    /// Error of a malformed field `field` described by `reason`.
+    pub fn field<S: Into<String>>(field: &str, reason: S) -> Error {
+        Error::Parse {
+            line: None,
+            field: Some(field.to_string()),
+            reason: reason.into(),
+            source: None,
+        }
+    }
+
+    /// Error of a malformed field `field` caused by error `source`.
+    pub fn field_source<E>(field: &str, source: E) -> Error
+    where
+        E: error::Error + Send + Sync + 'static,
+    {
+        Error::Parse {
+            line: None,
+            field: Some(field.to_string()),
+            reason: source.to_string(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn state<S: Into<String>>(reason: S) -> Error {
+        Error::State(reason.into())
+    }
+
+    /// Sets one based number of the malformed line of a parse error. Other
+    /// errors are returned unchanged.
+    pub fn at_line(self, number: usize) -> Error {
+        match self {
+            Error::Parse {
+                field,
+                reason,
+                source,
+                ..
+            } => Error::Parse {
+                line: Some(number),
+                field,
+                reason,
+                source,
+            },
+            error => error,
+        }
+    }
+
+    /// Number of the malformed line of a parse error.
+    pub fn line(&self) -> Option<usize> {
+        match *self {
+            Error::Parse { line, .. } => line,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io {
+                operation,
+                path,
+                source,
+            } => write!(
+                f,
+                "Couldn't {} \"{}\": {}",
+                operation,
+                path.to_string_lossy(),
+                source
+            ),
+            Error::Network {
+                operation,
+                address,
+                source,
+            } => write!(f, "Couldn't {} {}: {}", operation, address, source),
+            Error::Parse {
+                line,
+                field,
+                reason,
+                ..
+            } => {
+                if let Some(line) = line {
+                    write!(f, "Error on line {}: ", line)?;
+                }
+                if let Some(field) = field {
+                    write!(f, "Failed to parse {}: ", field)?;
+                }
+                write!(f, "{}", reason)
+            }
+            Error::State(reason) => write!(f, "{}", reason),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(source) => write!(f, "SQLite storage failed: {}", source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Network { source, .. } => Some(source),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(source) => Some(source),
+            Error::Parse {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_display() {
+        let error = Error::io(
+            "open file",
+            Path::new("/cards.txt"),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(
+            error.to_string(),
+            "Couldn't open file \"/cards.txt\": not found"
+        );
+        assert!(error.source().is_some());
+
+        let error = Error::field_source("count", "x".parse::<u32>().unwrap_err()).at_line(3);
+        assert_eq!(
+            error.to_string(),
+            "Error on line 3: Failed to parse count: invalid digit found in string"
+        );
+        assert_eq!(error.line(), Some(3));
+        assert!(error.source().is_some());
+
+        let error = Error::state("There is no note.").at_line(1);
+        assert_eq!(error.to_string(), "There is no note.");
+        assert_eq!(error.line(), None);
+        assert!(error.source().is_none());
+    }
+}
@@ -15,94 +15,563 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::backup::Backup;
+use crate::calendar::Calendar;
 use crate::card::Card;
+use crate::error::Error;
+use crate::media::{Media, MediaKind, Side};
+use crate::note::{Note, NoteType};
+use crate::progress::Progress;
+use crate::review::Review;
+use crate::scheduler::ScheduleItem;
+use crate::storage::Storage;
 use dirs;
-use std::fs::{create_dir, File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::fs::{create_dir, read_to_string, remove_file, rename, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// All VoLe files are place to a directory inside users home directory. This
 /// is name of the directory.
 const VOLE_DIR_NAME: &str = ".vole";
 /// File name of the file storing all cards.
 const CARDS_FILE_NAME: &str = "cards.txt";
+/// File name of the file storing all notes.
+const NOTES_FILE_NAME: &str = "notes.txt";
+/// File name of the file with user defined note types.
+const NOTE_TYPES_FILE_NAME: &str = "types.txt";
+/// File name of the file storing schedule of all learned cards.
+const SCHEDULE_FILE_NAME: &str = "schedule.txt";
+/// File name of the file storing today's learning progress.
+const PROGRESS_FILE_NAME: &str = "progress.txt";
+/// File name of the file storing log of all reviews.
+const REVIEWS_FILE_NAME: &str = "reviews.txt";
+
+/// Format of cards file.
+pub const CARDS_FORMAT: Format = Format {
+    file_name: CARDS_FILE_NAME,
+    name: "vole-cards",
+    version: 2,
+};
+/// Format of schedule file.
+pub const SCHEDULE_FORMAT: Format = Format {
+    file_name: SCHEDULE_FILE_NAME,
+    name: "vole-schedule",
+    version: 2,
+};
+/// Format of reviews file. The file has a header since its first version.
+pub const REVIEWS_FORMAT: Format = Format {
+    file_name: REVIEWS_FILE_NAME,
+    name: "vole-reviews",
+    version: 1,
+};
+
+/// Versioned format of a file inside VoLe directory. Files start with a
+/// header line of format name and version, e.g. `#vole-cards 2`. Cards and
+/// schedule files written before the header was introduced are of version 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Format {
+    file_name: &'static str,
+    name: &'static str,
+    version: u32,
+}
+
+impl Format {
+    pub fn file_name(&self) -> &'static str {
+        self.file_name
+    }
+
+    /// Version written by this version of VoLe.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Header line of the current version (ending with line-feed).
+    pub fn header(&self) -> String {
+        format!("#{} {}\n", self.name, self.version)
+    }
+
+    /// Parse version from the first line of a file. Lines not starting with
+    /// `#` are records of a version 1 file.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the header is of another format or of a
+    /// version newer than this version of VoLe reads.
+    pub fn parse_version(&self, first_line: &str) -> Result<u32, Error> {
+        let header = match first_line.trim_end().strip_prefix('#') {
+            Some(header) => header,
+            None => return Ok(1),
+        };
+
+        let version = header
+            .strip_prefix(self.name)
+            .and_then(|rest| rest.strip_prefix(' '))
+            .and_then(|version| version.parse::<u32>().ok());
+        match version {
+            Some(version) if version <= self.version => Ok(version),
+            Some(version) => Err(Error::state(format!(
+                "{} is of format version {}, this version of VoLe reads at most \
+                 version {}. Please upgrade VoLe.",
+                self.file_name, version, self.version
+            ))),
+            None => Err(Error::parse(format!(
+                "Expected {} header, got: {}",
+                self.name,
+                first_line.trim_end()
+            ))),
+        }
+    }
+
+    /// Returns numbered lines (starting at one) of records in `content` of a
+    /// file of any supported version, i.e. without the header line.
+    pub fn records<'a>(&self, content: &'a str) -> Result<Vec<(usize, &'a str)>, Error> {
+        let mut lines = content.lines().enumerate().peekable();
+        if let Some(&(_, first_line)) = lines.peek() {
+            self.parse_version(first_line)
+                .map_err(|error| error.at_line(1))?;
+            if first_line.starts_with('#') {
+                lines.next();
+            }
+        }
+        Ok(lines.map(|(i, line)| (i + 1, line)).collect())
+    }
+
+    /// Reads version of the file in VoLe directory. Empty files are of the
+    /// current version.
+    pub fn read_version(&self) -> Result<u32, Error> {
+        let path = get_file_path(self.file_name)?;
+        let content =
+            read_to_string(&path).map_err(|error| Error::io("read file", &path, error))?;
+        match content.lines().next() {
+            Some(first_line) => self
+                .parse_version(first_line)
+                .map_err(|error| error.at_line(1)),
+            None => Ok(self.version),
+        }
+    }
+}
 
 impl Card {
     /// Serialize `Card` to a `String` of a single line; with line-feed at the
     /// end.
+    ///
+    /// The line consists of TAB separated ID, question and answer optionally
+    /// followed by `name=value` attributes, e.g. `siblings=<ID>,<ID>` or
+    /// `note=<ID>:<template index>`.
     pub fn to_line(&self) -> String {
         let id = Card::serialize_id(self.id());
-        format!("{}\t{}\t{}\n", id, self.question(), self.answer())
+        let mut line = format!("{}\t{}\t{}", id, self.question(), self.answer());
+
+        if !self.alternatives().is_empty() {
+            let alternatives: Vec<String> = self
+                .alternatives()
+                .iter()
+                .map(|alternative| escape(alternative))
+                .collect();
+            line.push_str("\talternatives=");
+            line.push_str(&alternatives.join("|"));
+        }
+
+        if let Some(hint) = self.hint() {
+            line.push_str(&format!("\thint={}", escape(hint)));
+        }
+
+        for &side in &[Side::Question, Side::Answer] {
+            for &kind in &[MediaKind::Audio, MediaKind::Image] {
+                let files: Vec<String> = self
+                    .media()
+                    .iter()
+                    .filter(|media| media.side() == side && media.kind() == kind)
+                    .map(|media| escape(media.file()))
+                    .collect();
+                if !files.is_empty() {
+                    let attribute = Media::attribute(kind, side);
+                    line.push_str(&format!("\t{}={}", attribute, files.join("|")));
+                }
+            }
+        }
+
+        if let Some((note, template)) = self.note() {
+            line.push_str(&format!("\tnote={}:{}", Card::serialize_id(note), template));
+        }
+
+        if !self.siblings().is_empty() {
+            let siblings: Vec<String> = self
+                .siblings()
+                .iter()
+                .map(|&id| Card::serialize_id(id))
+                .collect();
+            line.push_str("\tsiblings=");
+            line.push_str(&siblings.join(","));
+        }
+
+        line.push('\n');
+        line
     }
 
     /// Parse `Card` from a `&str` of a single line (ending with line-feed).
-    fn from_line(line: &str) -> Result<Card, String> {
+    pub fn from_line(line: &str) -> Result<Card, Error> {
         let parts: Vec<&str> = line.trim().split('\t').collect();
 
-        if parts.len() != 3 {
-            let reason = format!("Expected three TAB separated tokens, got: {}", line);
-            return Err(reason);
+        if parts.len() < 3 {
+            let reason = format!(
+                "Expected at least three TAB separated tokens, got: {}",
+                line
+            );
+            return Err(Error::parse(reason));
         }
 
         let id: u64 = Card::parse_id(parts[0])?;
         let question = parts[1].to_string();
         let answer = parts[2].to_string();
-        Ok(Card::new(id, question, answer))
+        let mut card = Card::new(id, question, answer);
+
+        for attribute in &parts[3..] {
+            let mut attribute_parts = attribute.splitn(2, '=');
+            let name = attribute_parts.next().unwrap();
+            let value = match attribute_parts.next() {
+                Some(value) => value,
+                None => {
+                    let reason = format!("Expected name=value attribute, got: {}", attribute);
+                    return Err(Error::parse(reason));
+                }
+            };
+
+            match name {
+                "alternatives" => {
+                    for alternative in split_escaped(value) {
+                        card.add_alternative(alternative)?;
+                    }
+                }
+                "hint" => card.set_hint(unescape(value)),
+                "audio" | "image" | "answer_audio" | "answer_image" => {
+                    let (kind, side) = Media::parse_attribute(name).unwrap();
+                    for file in split_escaped(value) {
+                        card.add_media(Media::new(kind, side, file));
+                    }
+                }
+                "siblings" => {
+                    for sibling in value.split(',') {
+                        card.add_sibling(Card::parse_id(sibling)?);
+                    }
+                }
+                "note" => {
+                    let mut note_parts = value.splitn(2, ':');
+                    let note = Card::parse_id(note_parts.next().unwrap())?;
+                    let template: usize = match note_parts.next().map(str::parse) {
+                        Some(Ok(template)) => template,
+                        _ => return Err(Error::field("note reference", value)),
+                    };
+                    card.set_note(note, template);
+                }
+                _ => return Err(Error::parse(format!("Unknown card attribute: {}", name))),
+            }
+        }
+
+        Ok(card)
     }
 }
 
+/// Escapes backslash, `|` separating alternatives and media files, TAB
+/// separating attributes and line-feed separating cards in attribute value
+/// `value`.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Splits `|` separated values escaped by `escape`. Backslashes not followed
+/// by an escaped character are kept.
+fn split_escaped(value: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        let current = values.last_mut().unwrap();
+        match (c, chars.peek()) {
+            ('\\', Some(&escaped)) if escaped == '\\' || escaped == '|' => {
+                current.push(escaped);
+                chars.next();
+            }
+            ('\\', Some('t')) => {
+                current.push('\t');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                current.push('\n');
+                chars.next();
+            }
+            ('|', _) => values.push(String::new()),
+            _ => current.push(c),
+        }
+    }
+    values
+}
+
+/// Unescapes a single value escaped by `escape`, unescaped `|` are kept.
+fn unescape(value: &str) -> String {
+    split_escaped(value).join("|")
+}
+
+/// Storage in text files inside VoLe directory: `cards.txt`, `schedule.txt`,
+/// `reviews.txt` and `progress.txt` with one card, schedule item, review or
+/// progress record per line. Cards, schedule and reviews files start with a
+/// header of their format version, see `Format`. Rewritten files are sorted
+/// by card IDs and VoLe directory is backed up before the first rewrite.
+#[derive(Debug, Default)]
+pub struct TextStorage {
+    backup: Backup,
+}
+
+impl TextStorage {
+    /// Creates text storage which backs up VoLe directory with `backup`.
+    pub fn new(backup: Backup) -> TextStorage {
+        TextStorage { backup }
+    }
+}
+
+impl Storage for TextStorage {
+    fn read_cards(&self) -> Result<Vec<Card>, Error> {
+        read_cards()?.collect()
+    }
+
+    fn store_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        store_cards(cards)
+    }
+
+    fn rewrite_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        self.backup()?;
+        rewrite_cards(cards)
+    }
+
+    fn read_schedule(&self, calendar: &Calendar) -> Result<Vec<(u64, ScheduleItem)>, Error> {
+        let path = get_schedule_file_path()?;
+        let content =
+            read_to_string(&path).map_err(|error| Error::io("read file", &path, error))?;
+
+        SCHEDULE_FORMAT
+            .records(&content)?
+            .into_iter()
+            .map(|(number, line)| {
+                ScheduleItem::deserialize(line, calendar).map_err(|error| error.at_line(number))
+            })
+            .collect()
+    }
+
+    fn write_schedule(&mut self, items: &[(u64, &ScheduleItem)]) -> Result<(), Error> {
+        self.backup()?;
+        let path = get_schedule_file_path()?;
+        let mut items = items.to_vec();
+        items.sort_by_key(|&(id, _)| id);
+        let mut lines = vec![SCHEDULE_FORMAT.header()];
+        lines.extend(items.iter().map(|&(id, item)| item.serialize(id)));
+        rewrite_lines(&path, &lines)
+    }
+
+    fn read_reviews(&self) -> Result<Vec<Review>, Error> {
+        let path = get_file_path(REVIEWS_FILE_NAME)?;
+        let content =
+            read_to_string(&path).map_err(|error| Error::io("read file", &path, error))?;
+
+        REVIEWS_FORMAT
+            .records(&content)?
+            .into_iter()
+            .map(|(number, line)| Review::deserialize(line).map_err(|error| error.at_line(number)))
+            .collect()
+    }
+
+    fn store_reviews(&mut self, reviews: &[Review]) -> Result<(), Error> {
+        if reviews.is_empty() {
+            return Ok(());
+        }
+        let path = get_file_path(REVIEWS_FILE_NAME)?;
+        let mut lines: Vec<String> = Vec::with_capacity(reviews.len() + 1);
+        let metadata = path
+            .metadata()
+            .map_err(|error| Error::io("read metadata of", &path, error))?;
+        if metadata.len() == 0 {
+            lines.push(REVIEWS_FORMAT.header());
+        }
+        lines.extend(reviews.iter().map(Review::serialize));
+        append_lines(&path, &lines)
+    }
+
+    fn read_progress(&self) -> Result<Option<Progress>, Error> {
+        let path = get_file_path(PROGRESS_FILE_NAME)?;
+        let content =
+            read_to_string(&path).map_err(|error| Error::io("read file", &path, error))?;
+        if content.trim().is_empty() {
+            return Ok(None);
+        }
+        Progress::deserialize(content.trim()).map(Some)
+    }
+
+    fn write_progress(&mut self, progress: &Progress) -> Result<(), Error> {
+        let path = get_file_path(PROGRESS_FILE_NAME)?;
+        rewrite_lines(&path, &[progress.serialize()])
+    }
+
+    fn backup(&mut self) -> Result<(), Error> {
+        self.backup.create()
+    }
+}
+
+/// Removes cards, schedule, reviews and progress files of text storage, e.g.
+/// after migration to another storage.
+pub fn remove_text_storage() -> Result<(), Error> {
+    let vole_dir = get_vole_dir()?;
+    for name in &[
+        CARDS_FILE_NAME,
+        SCHEDULE_FILE_NAME,
+        REVIEWS_FILE_NAME,
+        PROGRESS_FILE_NAME,
+    ] {
+        let path = vole_dir.join(name);
+        match remove_file(&path) {
+            Ok(()) => (),
+            Err(ref error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => return Err(Error::io("remove file", &path, error)),
+        }
+    }
+    Ok(())
+}
+
 /// Append a slice `Card`-s into cards file. This opens cards wile in append
-/// mode and writes at the end of it.
-pub fn store_cards(cards: &[Card]) -> Result<(), String> {
+/// mode and writes at the end of it. A header is written first to an empty
+/// file.
+pub fn store_cards(cards: &[Card]) -> Result<(), Error> {
     let cards_file_path = get_cards_file_path()?;
+    let mut lines: Vec<String> = Vec::with_capacity(cards.len() + 1);
+    let metadata = cards_file_path
+        .metadata()
+        .map_err(|error| Error::io("read metadata of", &cards_file_path, error))?;
+    if metadata.len() == 0 {
+        lines.push(CARDS_FORMAT.header());
+    }
+    lines.extend(cards.iter().map(Card::to_line));
+    append_lines(&cards_file_path, &lines)
+}
 
-    let mut open_options = OpenOptions::new();
-    open_options.append(true);
-    let mut writer = match open_options.open(&cards_file_path) {
-        Ok(file) => BufWriter::new(file),
-        Err(error) => {
-            let reason = format!(
-                "Couldn't open file \"{}\": {}",
-                cards_file_path.to_string_lossy(),
-                error
-            );
-            return Err(reason);
-        }
+/// Replace content of cards file with a slice of `Card`-s. The cards are
+/// written sorted by their IDs so that the file changes as little as possible
+/// (e.g. when it is kept in git) after a header of the current format.
+pub fn rewrite_cards(cards: &[Card]) -> Result<(), Error> {
+    let cards_file_path = get_cards_file_path()?;
+    let mut cards: Vec<&Card> = cards.iter().collect();
+    cards.sort_by_key(|card| card.id());
+    let mut lines = vec![CARDS_FORMAT.header()];
+    lines.extend(cards.into_iter().map(Card::to_line));
+    rewrite_lines(&cards_file_path, &lines)
+}
+
+/// Append a slice of `Note`-s into notes file.
+pub fn store_notes(notes: &[Note]) -> Result<(), Error> {
+    let notes_file_path = get_file_path(NOTES_FILE_NAME)?;
+    let lines: Vec<String> = notes.iter().map(Note::to_line).collect();
+    append_lines(&notes_file_path, &lines)
+}
+
+/// Replace content of notes file with a slice of `Note`-s sorted by their
+/// IDs. VoLe directory is not backed up, see `Storage::backup`.
+pub fn rewrite_notes(notes: &[Note]) -> Result<(), Error> {
+    let notes_file_path = get_file_path(NOTES_FILE_NAME)?;
+    let mut notes: Vec<&Note> = notes.iter().collect();
+    notes.sort_by_key(|note| note.id());
+    let lines: Vec<String> = notes.into_iter().map(Note::to_line).collect();
+    rewrite_lines(&notes_file_path, &lines)
+}
+
+/// Load all notes from notes file.
+pub fn read_notes() -> Result<Vec<Note>, Error> {
+    let notes_file_path = get_file_path(NOTES_FILE_NAME)?;
+    let content = read_to_string(&notes_file_path)
+        .map_err(|error| Error::io("read file", &notes_file_path, error))?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| Note::from_line(line).map_err(|error| error.at_line(i + 1)))
+        .collect()
+}
+
+/// Load built-in note types and note types defined by user in note types
+/// file. User defined note types take precedence over built-in note types of
+/// the same name.
+pub fn read_note_types() -> Result<Vec<NoteType>, Error> {
+    let mut path = get_vole_dir()?;
+    path.push(NOTE_TYPES_FILE_NAME);
+
+    let mut note_types = match read_to_string(&path) {
+        Ok(content) => NoteType::parse(&content)?,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(Error::io("read file", &path, error)),
     };
 
-    for card in cards {
-        if let Err(error) = writer.write_all(card.to_line().as_bytes()) {
-            let reason = format!(
-                "Couldn't append to file \"{}\": {}",
-                cards_file_path.to_string_lossy(),
-                error
-            );
-            return Err(reason);
+    for built_in in [
+        NoteType::basic(),
+        NoteType::bidirectional(),
+        NoteType::cloze(),
+    ] {
+        if note_types
+            .iter()
+            .all(|other| other.name() != built_in.name())
+        {
+            note_types.push(built_in);
         }
     }
 
-    if let Err(why) = writer.flush() {
-        return Err(format!(
-            "Couldn't append to file \"{}\": {}",
-            cards_file_path.to_string_lossy(),
-            why
-        ));
+    Ok(note_types)
+}
+
+/// Append lines (each ending with line-feed) to file at `path`.
+fn append_lines(path: &Path, lines: &[String]) -> Result<(), Error> {
+    let mut open_options = OpenOptions::new();
+    open_options.append(true);
+    let file = open_options
+        .open(path)
+        .map_err(|error| Error::io("open file", path, error))?;
+    write_lines(BufWriter::new(file), path, lines)
+}
+
+/// Replace content of file at `path` with lines (each ending with
+/// line-feed). The lines are written to a temporary file first which is then
+/// renamed so the file is never left half written.
+pub fn rewrite_lines(path: &Path, lines: &[String]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("txt.tmp");
+
+    let file = File::create(&tmp_path).map_err(|error| Error::io("open file", &tmp_path, error))?;
+    write_lines(BufWriter::new(file), &tmp_path, lines)?;
+
+    rename(&tmp_path, path).map_err(|error| Error::io("rename file", &tmp_path, error))
+}
+
+/// Write lines to `writer` of file at `path` and flush it.
+fn write_lines(mut writer: BufWriter<File>, path: &Path, lines: &[String]) -> Result<(), Error> {
+    for line in lines {
+        writer
+            .write_all(line.as_bytes())
+            .map_err(|error| Error::io("append to file", path, error))?;
     }
 
-    Ok(())
+    writer
+        .flush()
+        .map_err(|error| Error::io("append to file", path, error))
 }
 
 pub struct CardsReader {
     error: bool,
+    path: PathBuf,
     reader: BufReader<File>,
     line_nr: usize,
 }
 
 impl Iterator for CardsReader {
-    type Item = Result<Card, String>;
+    type Item = Result<Card, Error>;
 
-    fn next(&mut self) -> Option<Result<Card, String>> {
+    fn next(&mut self) -> Option<Result<Card, Error>> {
         if self.error {
             return None;
         }
@@ -111,8 +580,7 @@ impl Iterator for CardsReader {
 
         if let Err(error) = self.reader.read_line(&mut line) {
             self.error = true;
-            let result = Err(format!("Couldn't read card file: {}", error));
-            return Some(result);
+            return Some(Err(Error::io("read file", &self.path, error)));
         }
 
         if line.is_empty() {
@@ -121,9 +589,20 @@ impl Iterator for CardsReader {
 
         self.line_nr += 1;
 
+        if self.line_nr == 1 {
+            match CARDS_FORMAT.parse_version(&line) {
+                Ok(1) => (),
+                Ok(_) => return self.next(),
+                Err(error) => {
+                    self.error = true;
+                    return Some(Err(error.at_line(1)));
+                }
+            }
+        }
+
         let result = Card::from_line(&line).map_err(|error| {
             self.error = true;
-            format!("Error on line {}: {}", self.line_nr, error)
+            error.at_line(self.line_nr)
         });
 
         Some(result)
@@ -131,14 +610,15 @@ impl Iterator for CardsReader {
 }
 
 /// Load cards gradually in form of an iterator from cards file.
-pub fn read_cards() -> Result<CardsReader, String> {
+pub fn read_cards() -> Result<CardsReader, Error> {
     let cards_file_path = get_cards_file_path()?;
 
     let file = File::open(&cards_file_path)
-        .map_err(|error| format!("Couldn't open card file: {}", error))?;
+        .map_err(|error| Error::io("open file", &cards_file_path, error))?;
 
     Ok(CardsReader {
         error: false,
+        path: cards_file_path,
         reader: BufReader::new(file),
         line_nr: 0,
     })
@@ -149,20 +629,33 @@ pub fn read_cards() -> Result<CardsReader, String> {
 ///
 /// # Errors
 ///
-/// In case of an I/O or other error a `String` with reason is returned.
-fn get_cards_file_path() -> Result<PathBuf, String> {
+/// In case of an I/O or other error an `Error` with reason is returned.
+pub fn get_cards_file_path() -> Result<PathBuf, Error> {
+    get_file_path(CARDS_FILE_NAME)
+}
+
+/// This returns path to user's schedule file and creates vole directory and
+/// schedule file along the way if necessary.
+///
+/// # Errors
+///
+/// In case of an I/O or other error an `Error` with reason is returned.
+pub fn get_schedule_file_path() -> Result<PathBuf, Error> {
+    get_file_path(SCHEDULE_FILE_NAME)
+}
+
+/// This returns path to a file inside vole directory and creates vole
+/// directory and an empty file along the way if necessary.
+///
+/// # Errors
+///
+/// In case of an I/O or other error an `Error` with reason is returned.
+fn get_file_path(file_name: &str) -> Result<PathBuf, Error> {
     let mut file_path = get_vole_dir()?;
 
-    file_path.push(&CARDS_FILE_NAME);
+    file_path.push(file_name);
     if !file_path.exists() {
-        if let Err(error) = File::create(&file_path) {
-            let reason = format!(
-                "Couldn't create \"{}\" file: {}",
-                file_path.to_string_lossy(),
-                error
-            );
-            return Err(reason);
-        }
+        File::create(&file_path).map_err(|error| Error::io("create file", &file_path, error))?;
     }
 
     Ok(file_path)
@@ -171,23 +664,137 @@ fn get_cards_file_path() -> Result<PathBuf, String> {
 /// Get directory where VoLe configuration and other files are stored (possible
 /// deeper in the hierarchy). This function creates the directory if it doesn't
 /// exist.
-pub fn get_vole_dir() -> Result<PathBuf, String> {
+pub fn get_vole_dir() -> Result<PathBuf, Error> {
     let mut file_path = match dirs::home_dir() {
         Some(path_buf) => path_buf,
-        None => return Err("Couldn't locate home directory.".to_string()),
+        None => return Err(Error::state("Couldn't locate home directory.")),
     };
 
-    file_path.push(&VOLE_DIR_NAME);
+    file_path.push(VOLE_DIR_NAME);
     if !file_path.exists() {
-        if let Err(error) = create_dir(&file_path) {
-            let reason = format!(
-                "Couldn't create \"{}\" directory: {}",
-                file_path.to_string_lossy(),
-                error
-            );
-            return Err(reason);
-        }
+        create_dir(&file_path).map_err(|error| Error::io("create directory", &file_path, error))?;
     }
 
     Ok(file_path)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let card = Card::from_line("000000000000000a\tWhat?\tSomething!\n").unwrap();
+        assert_eq!(card.id(), 10);
+        assert_eq!(card.question(), "What?");
+        assert_eq!(card.answer(), "Something!");
+        assert!(card.siblings().is_empty());
+        assert_eq!(card.to_line(), "000000000000000a\tWhat?\tSomething!\n");
+
+        let line = "000000000000000a\tWhat?\tSomething!\tsiblings=000000000000000b\n";
+        let card = Card::from_line(line).unwrap();
+        assert_eq!(card.siblings(), &[11]);
+        assert_eq!(card.to_line(), line);
+
+        let line = "000000000000000a\tHund\tdog\talternatives=hound|canine\thint=animal\n";
+        let card = Card::from_line(line).unwrap();
+        assert_eq!(card.answers(), vec!["dog", "hound", "canine"]);
+        assert_eq!(card.hint(), Some("animal"));
+        assert_eq!(card.to_line(), line);
+
+        let mut card = Card::new(10, "Hund".to_string(), "dog".to_string());
+        card.add_alternative("dog | hound".to_string()).unwrap();
+        card.add_alternative("a\tb \\*".to_string()).unwrap();
+        let line = "000000000000000a\tHund\tdog\talternatives=dog \\| hound|a\\tb \\\\*\n";
+        assert_eq!(card.to_line(), line);
+        assert_eq!(Card::from_line(line).unwrap(), card);
+        assert_eq!(
+            Card::from_line("000000000000000a\tHund\tdog\talternatives=hound||canine\n")
+                .unwrap_err()
+                .to_string(),
+            "Alternative answer can't be empty."
+        );
+
+        let mut card = Card::new(10, "Hund".to_string(), "dog".to_string());
+        card.set_hint("a\tb\nc | d \\*".to_string());
+        let line = "000000000000000a\tHund\tdog\thint=a\\tb\\nc \\| d \\\\*\n";
+        assert_eq!(card.to_line(), line);
+        assert_eq!(Card::from_line(line).unwrap(), card);
+        let card = Card::from_line("000000000000000a\tHund\tdog\thint=a|b \\*\n").unwrap();
+        assert_eq!(card.hint(), Some("a|b \\*"));
+
+        let line = "000000000000000a\tHund\tdog\taudio=hund.mp3\tanswer_image=dog.png|dogs.png\n";
+        let card = Card::from_line(line).unwrap();
+        assert_eq!(
+            card.media()[0],
+            Media::new(MediaKind::Audio, Side::Question, "hund.mp3".to_string())
+        );
+        assert_eq!(card.media().len(), 3);
+        assert_eq!(card.to_line(), line);
+
+        let mut card = Card::new(10, "Hund".to_string(), "dog".to_string());
+        card.add_media(Media::new(
+            MediaKind::Audio,
+            Side::Question,
+            "a|b.mp3".to_string(),
+        ));
+        card.add_media(Media::new(
+            MediaKind::Audio,
+            Side::Question,
+            "c\td\n.mp3".to_string(),
+        ));
+        let line = "000000000000000a\tHund\tdog\taudio=a\\|b.mp3|c\\td\\n.mp3\n";
+        assert_eq!(card.to_line(), line);
+        assert_eq!(Card::from_line(line).unwrap(), card);
+
+        assert_eq!(
+            Card::from_line("000000000000000a\tWhat?\n")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Expected at least three TAB separated tokens, got: 000000000000000a\tWhat?\n"
+        );
+        assert_eq!(
+            Card::from_line("000000000000000a\tWhat?\tSomething!\tcolor=red\n")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unknown card attribute: color"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(CARDS_FORMAT.header(), "#vole-cards 2\n");
+        assert_eq!(CARDS_FORMAT.parse_version("#vole-cards 2\n").unwrap(), 2);
+        assert_eq!(
+            CARDS_FORMAT
+                .parse_version("000000000000000a\tHund\tdog\n")
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            CARDS_FORMAT
+                .parse_version("#vole-cards 3")
+                .err()
+                .unwrap()
+                .to_string(),
+            "cards.txt is of format version 3, this version of VoLe reads at most \
+             version 2. Please upgrade VoLe."
+        );
+        assert_eq!(
+            SCHEDULE_FORMAT
+                .parse_version("#vole-cards 2")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Expected vole-schedule header, got: #vole-cards 2"
+        );
+
+        let records = CARDS_FORMAT.records("#vole-cards 2\na\nb\n").unwrap();
+        assert_eq!(records, vec![(2, "a"), (3, "b")]);
+        let records = CARDS_FORMAT.records("a\nb\n").unwrap();
+        assert_eq!(records, vec![(1, "a"), (2, "b")]);
+        assert!(CARDS_FORMAT.records("").unwrap().is_empty());
+    }
+}
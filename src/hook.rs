@@ -0,0 +1,76 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks running external programs, e.g. media players or text-to-speech
+//! engines, configured by the user.
+
+use crate::error::Error;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs command `command` split at white space with each `(placeholder,
+/// value)` substituted in all its arguments. This waits until the command
+/// finishes.
+pub fn run_command(command: &str, substitutions: &[(&str, &str)]) -> Result<(), Error> {
+    let mut args = command.split_whitespace().map(|arg| {
+        substitutions
+            .iter()
+            .fold(arg.to_string(), |arg, (placeholder, value)| {
+                arg.replace(placeholder, value)
+            })
+    });
+
+    let program = match args.next() {
+        Some(program) => program,
+        None => return Ok(()),
+    };
+
+    match Command::new(&program).args(args).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::state(format!(
+            "Command \"{}\" failed: {}",
+            program, status
+        ))),
+        Err(error) => Err(Error::io("run", Path::new(&program), error)),
+    }
+}
+
+/// Speaks `text` in language `language` (e.g. `de`) with text-to-speech
+/// command `command`. Placeholders `{text}` and `{lang}` in the command are
+/// replaced with the text and the language, e.g. `espeak-ng -v {lang} {text}`.
+pub fn speak(command: &str, text: &str, language: &str) -> Result<(), Error> {
+    run_command(command, &[("{text}", text), ("{lang}", language)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_command() {
+        assert!(run_command("true {file}", &[("{file}", "a b")]).is_ok());
+        assert!(run_command("", &[]).is_ok());
+        assert_eq!(
+            run_command("false", &[]).unwrap_err().to_string(),
+            "Command \"false\" failed: exit status: 1"
+        );
+        assert!(run_command("vole-nonexistent-command", &[])
+            .unwrap_err()
+            .to_string()
+            .starts_with("Couldn't run \"vole-nonexistent-command\": "));
+    }
+}
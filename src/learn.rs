@@ -15,14 +15,23 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use crate::file::read_cards;
+use crate::card::Card;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::error::Error;
+use crate::hook;
+use crate::lock::Lock;
+use crate::markup::{self, Style};
+use crate::media::{MediaKind, Side};
 use crate::prompt::{self, CmdOption, Command};
 use crate::qa::Qa;
+use crate::storage;
 
 #[derive(PartialEq, Clone)]
 enum UserAction {
     Continue,
     AddMore,
+    Speak,
     Quit,
 }
 
@@ -96,72 +105,204 @@ impl CmdOption for AssessmentOption {
 /// Start question answer loop. Questions, answers and options are printed to
 /// standard output and user commands are read from standard input. The loop
 /// continues until user enters end command.
-pub fn learning_loop() -> Result<(), String> {
-    let reader = read_cards()?;
-    let mut qa = Qa::load(reader)?;
+///
+/// New cards are introduced automatically up to the daily limit. User is
+/// asked whether to add more once the limit is reached and all scheduled
+/// cards are done. Cards are scheduled according to current time of `clock`.
+///
+/// Only one learning session may save its results at a time. It fails if
+/// another session is running unless `read_only` is set, in which case
+/// nothing is saved.
+pub fn learning_loop(clock: Box<dyn Clock>, read_only: bool) -> Result<(), Error> {
+    let _session = if read_only {
+        None
+    } else {
+        match Lock::session()? {
+            Some(lock) => Some(lock),
+            None => {
+                return Err(Error::state(
+                    "Another learning session is running. Use --read-only to \
+                     learn without saving the results.",
+                ))
+            }
+        }
+    };
+
+    let config = Config::load()?;
+    let mut qa = Qa::load(storage::open()?, &config, clock)?;
+    qa.schedule_new();
+    let style = Style::detect();
 
     let mut next_action = UserAction::Continue;
     while next_action != UserAction::Quit {
         if next_action == UserAction::AddMore {
             qa.schedule_more(8);
         }
-        next_action = iteration(&mut qa);
+        next_action = iteration(&mut qa, &config, style);
     }
 
-    qa.save()?;
+    if !read_only {
+        let _lock = Lock::write()?;
+        qa.save()?;
+    }
     Ok(())
 }
 
-fn iteration(qa: &mut Qa) -> UserAction {
+fn iteration(qa: &mut Qa, config: &Config, style: Style) -> UserAction {
     if qa.is_today_schedule_done() {
         ask_for_more(qa)
     } else {
-        show_card(qa)
+        show_card(qa, config, style)
     }
 }
 
 fn read_option(command: &Command<LoopOption>) -> UserAction {
-    prompt::prompt(&command)
+    prompt::prompt(command)
         .expect("Invalid option.")
         .action
         .clone()
 }
 
-fn show_card(qa: &mut Qa) -> UserAction {
+fn show_card(qa: &mut Qa, config: &Config, style: Style) -> UserAction {
     let yes = LoopOption {
         letter: 'y',
         doc: "yes".to_string(),
         action: UserAction::Continue,
     };
+    let speak_option = LoopOption {
+        letter: 's',
+        doc: "speak".to_string(),
+        action: UserAction::Speak,
+    };
     let quit = LoopOption {
         letter: 'q',
         doc: "quit".to_string(),
         action: UserAction::Quit,
     };
-    let options = vec![yes, quit];
+    let options = vec![yes.clone(), quit.clone()];
+    let speak_options = vec![yes, speak_option, quit];
 
-    {
+    let answer = {
         let card = qa.current_card();
-        println!("Q: {}", card.question());
-        let command = Command::new("Show answer", &options);
-        if read_option(&command) == UserAction::Quit {
-            return UserAction::Quit;
+        print_text("Q", card.question(), style);
+        if let Some(hint) = card.hint() {
+            print_text("H", hint, style);
+        }
+        show_media(card, Side::Question, config);
+
+        let question_language = config.question_language();
+        if config.speak_automatically() {
+            speak(card.question(), question_language, config);
+        }
+
+        if config.type_answer() {
+            if card.is_correct(&prompt::read_text("Your answer")) {
+                println!("Correct!");
+            } else {
+                println!("Wrong.");
+            }
+        } else {
+            let options = choose_options(question_language, config, &options, &speak_options);
+            let command = Command::new("Show answer", options);
+            loop {
+                match read_option(&command) {
+                    UserAction::Quit => return UserAction::Quit,
+                    UserAction::Speak => speak(card.question(), question_language, config),
+                    _ => break,
+                }
+            }
         }
 
-        println!("A: {}", card.answer());
+        let answer = card.answers().join("; ");
+        print_text("A", &answer, style);
+        show_media(card, Side::Answer, config);
+        answer
+    };
+
+    let answer_language = config.answer_language();
+    if config.speak_automatically() {
+        speak(&answer, answer_language, config);
     }
 
-    let command = Command::new("How difficult was it", &*ASSESSMENTS);
+    let command = Command::new("How difficult was it", &ASSESSMENTS);
     let q = prompt::prompt(&command).expect("Invalid option.").q;
     qa.assess_current(q);
 
-    let command = Command::new("Continue with another card", &options);
-    read_option(&command)
+    let options = choose_options(answer_language, config, &options, &speak_options);
+    let command = Command::new("Continue with another card", options);
+    loop {
+        match read_option(&command) {
+            UserAction::Speak => speak(&answer, answer_language, config),
+            action => return action,
+        }
+    }
+}
+
+/// Returns `speak_options` if text in language `language` can be spoken,
+/// `options` otherwise.
+fn choose_options<'a>(
+    language: Option<&str>,
+    config: &Config,
+    options: &'a [LoopOption],
+    speak_options: &'a [LoopOption],
+) -> &'a [LoopOption] {
+    if config.speak_command().is_some() && language.is_some() {
+        speak_options
+    } else {
+        options
+    }
+}
+
+/// Speak marked up `text` in language `language` with text-to-speech command
+/// from configuration. Nothing is spoken if either of them is not set.
+fn speak(text: &str, language: Option<&str>, config: &Config) {
+    if let (Some(command), Some(language)) = (config.speak_command(), language) {
+        let text = markup::render(text, Style::Plain).replace('\n', " ");
+        if let Err(reason) = hook::speak(command, &text, language) {
+            eprintln!("{}", reason);
+        }
+    }
+}
+
+/// Print marked up `text` labeled with `label`. Lines following the first
+/// one are aligned with it.
+fn print_text(label: &str, text: &str, style: Style) {
+    let indent = format!("\n{}", " ".repeat(label.len() + 2));
+    let text = markup::render(text, style).replace('\n', &indent);
+    println!("{}: {}", label, text);
+}
+
+/// Play or preview media attached to side `side` of `card` with commands
+/// from configuration. Only names of media files are printed when there is no
+/// command for them.
+fn show_media(card: &Card, side: Side, config: &Config) {
+    for media in card.media().iter().filter(|media| media.side() == side) {
+        let (label, command) = match media.kind() {
+            MediaKind::Audio => ("Audio", config.audio_command()),
+            MediaKind::Image => ("Image", config.image_command()),
+        };
+        match command {
+            Some(command) => {
+                if let Err(reason) = media.play(command) {
+                    eprintln!("{}", reason);
+                }
+            }
+            None => println!("{}: {}", label, media.file()),
+        }
+    }
 }
 
 fn ask_for_more(qa: &Qa) -> UserAction {
     if qa.is_all_scheduled() {
-        println!("This is it for today! There are no unscheduled cards.");
+        match qa.pending_learning() {
+            Some((count, next)) => println!(
+                "Come back later! {} card(s) in learning are due today, the \
+                 next one at {}.",
+                count,
+                next.format("%H:%M")
+            ),
+            None => println!("This is it for today! There are no unscheduled cards."),
+        }
         return UserAction::Quit;
     }
 
@@ -177,6 +318,6 @@ fn ask_for_more(qa: &Qa) -> UserAction {
     };
 
     let options = vec![yes, quit];
-    let command = Command::new("No more items planned for today, add more", &options);
+    let command = Command::new("Daily limit of new cards reached, add more", &options);
     read_option(&command)
 }
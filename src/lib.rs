@@ -21,9 +21,29 @@ extern crate fnv;
 extern crate lazy_static;
 extern crate rand;
 
+pub mod backup;
+pub mod calendar;
 pub mod card;
+pub mod check;
+pub mod clock;
+pub mod config;
+pub mod duplicate;
+pub mod editor;
+pub mod error;
 pub mod file;
+pub mod hook;
 pub mod learn;
+pub mod lock;
+pub mod markup;
+pub mod media;
+pub mod merge;
+pub mod note;
+pub mod progress;
 pub mod prompt;
 pub mod qa;
+pub mod review;
 pub mod scheduler;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
+pub mod sync;
@@ -0,0 +1,68 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error;
+use crate::file::get_vole_dir;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// File name of the lock file held during a learning session.
+const SESSION_LOCK_FILE_NAME: &str = "session.lock";
+/// File name of the lock file held while files in VoLe directory are being
+/// read and rewritten.
+const WRITE_LOCK_FILE_NAME: &str = "write.lock";
+
+/// An advisory lock of VoLe directory held until it is dropped. Locks are
+/// released by the operating system even if the process is killed.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Locks VoLe directory for a learning session. Returns `None` if another
+    /// process holds the session lock.
+    pub fn session() -> Result<Option<Lock>, Error> {
+        let path = get_vole_dir()?.join(SESSION_LOCK_FILE_NAME);
+        let file = open(&path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Lock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(error)) => Err(Error::io("lock file", &path, error)),
+        }
+    }
+
+    /// Locks VoLe directory for a read-modify-write operation, e.g. adding a
+    /// card. Waits until other processes release the lock.
+    pub fn write() -> Result<Lock, Error> {
+        let path = get_vole_dir()?.join(WRITE_LOCK_FILE_NAME);
+        let file = open(&path)?;
+        match file.lock() {
+            Ok(()) => Ok(Lock { _file: file }),
+            Err(error) => Err(Error::io("lock file", &path, error)),
+        }
+    }
+}
+
+/// Opens (and creates if necessary) lock file at `path`.
+fn open(path: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|error| Error::io("open file", path, error))
+}
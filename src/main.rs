@@ -19,10 +19,29 @@ extern crate clap;
 extern crate rand;
 extern crate vole;
 
+use chrono::{DateTime, Utc};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use regex::{self, Regex};
+use std::iter;
+use std::path::Path;
 use std::process;
-use vole::{card::Card, file, learn};
+use vole::{
+    backup,
+    card::{Card, IdGenerator},
+    check,
+    clock::{Clock, FixedClock, SystemClock},
+    config::Config,
+    duplicate, editor,
+    error::Error,
+    file, learn,
+    lock::Lock,
+    media::{self, Media, MediaKind, Side},
+    merge,
+    note::{Note, NoteType},
+    scheduler::ScheduleItem,
+    storage::{self, Backend},
+    sync,
+};
 
 fn main() {
     let app = App::new("VoLe")
@@ -31,6 +50,17 @@ fn main() {
         .about("CLI for flashcard learning")
         .setting(AppSettings::SubcommandRequired)
         .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("now")
+                .long("now")
+                .takes_value(true)
+                .global(true)
+                .hidden(true)
+                .help(
+                    "Overrides current time with a fixed RFC 3339 timestamp, \
+                     e.g. 2019-03-10T08:00:00Z.",
+                ),
+        )
         .subcommand(
             SubCommand::with_name("add")
                 .about("Stores a new flashcard.")
@@ -39,15 +69,158 @@ fn main() {
                         .long("bidirectional")
                         .short("b")
                         .help(
-                            "Stores a card bidirectionally, id est two \
-                             versions with answer and question swapped.",
+                            "Stores a card bidirectionally, id est a note of \
+                             type \"bidirectional\" generating two cards \
+                             with answer and question swapped.",
                         ),
                 )
-                .arg(Arg::with_name("question").required(true))
-                .arg(Arg::with_name("answer").required(true)),
+                .arg(
+                    Arg::with_name("alternative")
+                        .long("also")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .conflicts_with("bidir")
+                        .help("Another accepted answer, e.g. a synonym."),
+                )
+                .arg(
+                    Arg::with_name("hint")
+                        .long("hint")
+                        .takes_value(true)
+                        .conflicts_with("bidir")
+                        .help("A hint shown together with the question."),
+                )
+                .arg(
+                    Arg::with_name("audio")
+                        .long("audio")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .conflicts_with("bidir")
+                        .help("An audio file played with the question."),
+                )
+                .arg(
+                    Arg::with_name("image")
+                        .long("image")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .conflicts_with("bidir")
+                        .help("An image file shown with the question."),
+                )
+                .arg(
+                    Arg::with_name("answer_audio")
+                        .long("answer-audio")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .conflicts_with("bidir")
+                        .help("An audio file played with the answer."),
+                )
+                .arg(
+                    Arg::with_name("answer_image")
+                        .long("answer-image")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .conflicts_with("bidir")
+                        .help("An image file shown with the answer."),
+                )
+                .arg(
+                    Arg::with_name("duplicate")
+                        .long("duplicate")
+                        .takes_value(true)
+                        .possible_values(&["fail", "skip", "merge", "add"])
+                        .default_value("fail")
+                        .help(
+                            "What to do if a card with the same question \
+                             exists: fail, skip the new card, merge its \
+                             answers into the existing card or add it anyway.",
+                        ),
+                )
+                .arg(Arg::with_name("fuzzy").long("fuzzy").help(
+                    "Questions differing in a few characters are \
+                     duplicates too.",
+                ))
+                .arg(
+                    Arg::with_name("batch")
+                        .long("batch")
+                        .conflicts_with_all(&[
+                            "question",
+                            "alternative",
+                            "hint",
+                            "audio",
+                            "image",
+                            "answer_audio",
+                            "answer_image",
+                        ])
+                        .help("Writes more cards separated by empty lines in the editor."),
+                )
+                .arg(Arg::with_name("question").requires("answer").help(
+                    "Question of the card. Without question and answer, the \
+                     card is written in $VISUAL or $EDITOR.",
+                ))
+                .arg(Arg::with_name("answer")),
         )
         .subcommand(
-            SubCommand::with_name("learn").about("Starts question and answer learning loop."),
+            SubCommand::with_name("add-note")
+                .about(
+                    "Stores a new note and all flashcards generated from it \
+                     by templates of its note type.",
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .help(
+                            "Note type, either built-in \"basic\" or \
+                             \"bidirectional\" (with fields \"front\" and \
+                             \"back\"), \"cloze\" (with field \"text\") or a \
+                             type defined in ~/.vole/types.txt.",
+                        )
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .help("Field value in name=value format.")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("edit-note")
+                .about(
+                    "Changes fields of a note and updates all flashcards \
+                     generated from it.",
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .help("HEX ID of the note.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("field")
+                        .help("New field value in name=value format.")
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("learn")
+                .about("Starts question and answer learning loop.")
+                .arg(Arg::with_name("read_only").long("read-only").help(
+                    "Doesn't save the results, e.g. while another \
+                             learning session is running.",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("dedupe")
+                .about(
+                    "Prints groups of cards with the same question ignoring \
+                     letter case, white space, diacritics and markup.",
+                )
+                .arg(Arg::with_name("fuzzy").long("fuzzy").help(
+                    "Questions differing in a few characters are \
+                     duplicates too.",
+                )),
         )
         .subcommand(
             SubCommand::with_name("find")
@@ -63,6 +236,113 @@ fn main() {
                         )
                         .required(true),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about(
+                    "Manages backups of cards, notes, schedule and progress \
+                     made before they are rewritten.",
+                )
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    SubCommand::with_name("list").about("Lists all backups, the newest first."),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Replaces cards, notes, schedule and progress with a backup.")
+                        .arg(
+                            Arg::with_name("timestamp")
+                                .help("Name of the backup, e.g. 20190310T080000.000Z.")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about(
+                    "Checks cards and schedule for duplicate IDs, orphaned \
+                     schedule, malformed lines and impossible values.",
+                )
+                .arg(
+                    Arg::with_name("fix")
+                        .long("fix")
+                        .help("Repairs all problems which can be fixed safely."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about(
+                    "Upgrades files written by older versions of VoLe to the \
+                     current format or moves all cards, their schedule and \
+                     progress to another storage backend.",
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .possible_values(&["text", "sqlite"])
+                        .help(
+                            "Target backend, SQLite is available only if VoLe \
+                             is built with feature \"sqlite\".",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about(
+                    "Merges cards, notes, schedule and reviews with a sync \
+                     server, see serve-sync.",
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .help("Address of the server, e.g. 192.168.1.2:7373.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve-sync")
+                .about(
+                    "Runs a sync server merging cards, notes, schedule and \
+                     reviews of all devices running sync against it. The \
+                     server has no authentication.",
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .long("address")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:7373")
+                        .help("Address to listen on, e.g. 0.0.0.0:7373 for LAN."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge-driver")
+                .about(
+                    "Merges two versions of a VoLe file card by card, usable \
+                     as a git merge driver: vole merge-driver %O %A %B %P",
+                )
+                .arg(
+                    Arg::with_name("base")
+                        .help("The common ancestor version.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("ours")
+                        .help("Our version, replaced by the merged file.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("theirs")
+                        .help("Their version.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help(
+                            "Path of the merged file, e.g. cards.txt or \
+                             schedule.txt.",
+                        )
+                        .required(true),
+                ),
         );
 
     let matches = app.get_matches();
@@ -72,16 +352,41 @@ fn main() {
     }
 }
 
-fn execute(matches: ArgMatches) -> Result<(), String> {
+fn execute(matches: ArgMatches) -> Result<(), Error> {
     if let Some(matches) = matches.subcommand_matches("add") {
-        let question = matches.value_of("question").unwrap();
-        let answer = matches.value_of("answer").unwrap();
+        let (question, answer) = (matches.value_of("question"), matches.value_of("answer"));
+        if let (Some(question), Some(answer)) = (question, answer) {
+            let _lock = Lock::write()?;
+            return add_cards(vec![(question.to_string(), answer.to_string())], matches);
+        }
 
-        return if matches.is_present("bidir") {
-            add(&[(question, answer), (answer, question)])
+        let batch = matches.is_present("batch");
+        let entries = editor::edit_cards(batch)?;
+        if entries.is_empty() {
+            println!("No cards added.");
         } else {
-            add(&[(question, answer)])
-        };
+            let _lock = Lock::write()?;
+            add_cards(entries, matches).map_err(|error| editor::keep(error, batch))?;
+        }
+        return editor::discard(batch);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("add-note") {
+        let note_type = matches.value_of("type").unwrap();
+        let fields = parse_fields(matches)?;
+        let _lock = Lock::write()?;
+        return add_note(note_type, &fields);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("edit-note") {
+        let id = Card::parse_id(matches.value_of("id").unwrap())?;
+        let fields = parse_fields(matches)?;
+        let _lock = Lock::write()?;
+        return edit_note(id, &fields);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("dedupe") {
+        return dedupe(matches.is_present("fuzzy"));
     }
 
     if let Some(matches) = matches.subcommand_matches("find") {
@@ -89,24 +394,90 @@ fn execute(matches: ArgMatches) -> Result<(), String> {
         return find(regexp);
     }
 
-    matches.subcommand_matches("learn").unwrap();
-    learn::learning_loop()?;
+    if let Some(matches) = matches.subcommand_matches("backup") {
+        if matches.subcommand_matches("list").is_some() {
+            for name in backup::list()? {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+
+        let matches = matches.subcommand_matches("restore").unwrap();
+        let _session = match Lock::session()? {
+            Some(lock) => lock,
+            None => {
+                return Err(Error::state(
+                    "Can't restore a backup while a learning session is running.",
+                ))
+            }
+        };
+        let _lock = Lock::write()?;
+        return backup::restore(matches.value_of("timestamp").unwrap());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let now = clock(matches)?.now();
+        return check(now, matches.is_present("fix"));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let _session = match Lock::session()? {
+            Some(lock) => lock,
+            None => {
+                return Err(Error::state(
+                    "Can't migrate while a learning session is running.",
+                ))
+            }
+        };
+        let _lock = Lock::write()?;
+        return migrate(matches.value_of("to"));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("merge-driver") {
+        return merge_driver(matches);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("sync") {
+        let address = matches.value_of("address").unwrap();
+        let calendar = Config::load()?.calendar();
+        let _lock = Lock::write()?;
+        return sync::sync(address, storage::open()?.as_mut(), &calendar);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("serve-sync") {
+        let address = matches.value_of("address").unwrap();
+        return sync::serve(address, &Config::load()?);
+    }
+
+    let matches = matches.subcommand_matches("learn").unwrap();
+    learn::learning_loop(clock(matches)?, matches.is_present("read_only"))?;
     Ok(())
 }
 
-fn find(regex: &str) -> Result<(), String> {
+/// Returns system clock or a fixed clock if current time is overridden with
+/// `--now` option.
+fn clock(matches: &ArgMatches) -> Result<Box<dyn Clock>, Error> {
+    let now = match matches.value_of("now") {
+        Some(now) => now,
+        None => return Ok(Box::new(SystemClock)),
+    };
+
+    match DateTime::parse_from_rfc3339(now) {
+        Ok(now) => Ok(Box::new(FixedClock::new(now.with_timezone(&Utc)))),
+        Err(why) => Err(Error::field_source("time", why)),
+    }
+}
+
+fn find(regex: &str) -> Result<(), Error> {
     let regex = match Regex::new(regex) {
         Ok(regex) => regex,
-        Err(why) => return Err(format!("Invalid regex: {}", why)),
+        Err(why) => return Err(Error::field_source("regex", why)),
     };
 
-    let reader = file::read_cards()?;
-    for card in reader {
-        let card = match card {
-            Ok(card) => card,
-            Err(why) => return Err(why),
-        };
-        if !regex.is_match(card.question()) && !regex.is_match(card.answer()) {
+    for card in storage::open()?.read_cards()? {
+        if !regex.is_match(card.question())
+            && !card.answers().iter().any(|answer| regex.is_match(answer))
+        {
             continue;
         }
         print!("{}", card.to_line());
@@ -115,20 +486,368 @@ fn find(regex: &str) -> Result<(), String> {
     Ok(())
 }
 
-fn add(qa: &[(&str, &str)]) -> Result<(), String> {
-    let reader = file::read_cards()?;
-    let last_id: u64 = match reader.last() {
-        Some(Ok(card)) => card.id(),
-        Some(Err(error)) => return Err(error),
-        None => 0,
+/// Moves cards, schedule and progress to storage backend named `to` if
+/// given, otherwise upgrades files to the current format version.
+fn migrate(to: Option<&str>) -> Result<(), Error> {
+    let calendar = Config::load()?.calendar();
+    if let Some(to) = to {
+        return storage::migrate(Backend::parse(to)?, &calendar);
+    }
+
+    let upgraded = storage::upgrade(&calendar)?;
+    if upgraded.is_empty() {
+        println!("All files are up to date.");
+    }
+    for (file_name, version) in upgraded {
+        println!("Upgraded {} from format version {}.", file_name, version);
+    }
+    Ok(())
+}
+
+/// Prints problems found in cards and schedule and repairs the fixable ones
+/// if `fix` is true. Fails if any problem remains.
+fn check(now: DateTime<Utc>, fix: bool) -> Result<(), Error> {
+    let _lock = Lock::write()?;
+    let report = check::check(&Config::load()?.calendar(), now)?;
+    for problem in report.problems() {
+        println!("{}", problem);
+    }
+
+    let total = report.problems().len();
+    if total == 0 {
+        println!("No problems found.");
+        return Ok(());
+    }
+    let fixable = report
+        .problems()
+        .iter()
+        .filter(|problem| problem.is_fixable())
+        .count();
+    if fix && fixable > 0 {
+        report.fix()?;
+        println!("Fixed problems: {}", fixable);
+    }
+
+    let remaining = if fix { total - fixable } else { total };
+    match remaining {
+        0 => Ok(()),
+        _ if fix => Err(Error::state(format!(
+            "Problems left to fix manually: {}",
+            remaining
+        ))),
+        _ => Err(Error::state(format!(
+            "Problems found: {}, fixable with --fix: {}",
+            total, fixable
+        ))),
+    }
+}
+
+/// Merges files given by `matches` and fails if there are conflicting
+/// changes.
+fn merge_driver(matches: &ArgMatches) -> Result<(), Error> {
+    let conflicts = merge::merge_files(
+        Path::new(matches.value_of("base").unwrap()),
+        Path::new(matches.value_of("ours").unwrap()),
+        Path::new(matches.value_of("theirs").unwrap()),
+        Path::new(matches.value_of("path").unwrap()),
+        &Config::load()?.calendar(),
+    )?;
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let ids: Vec<String> = conflicts.into_iter().map(Card::serialize_id).collect();
+    Err(Error::state(format!(
+        "Conflicting changes of {}, our version kept: {}",
+        matches.value_of("path").unwrap(),
+        ids.join(", ")
+    )))
+}
+
+/// Parse `name=value` field arguments.
+fn parse_fields<'a>(matches: &'a ArgMatches) -> Result<Vec<(&'a str, &'a str)>, Error> {
+    matches
+        .values_of("field")
+        .unwrap()
+        .map(|field| {
+            let mut parts = field.splitn(2, '=');
+            let name = parts.next().unwrap();
+            match parts.next() {
+                Some(value) => Ok((name, value)),
+                None => Err(Error::parse(format!(
+                    "Expected name=value field, got: {}",
+                    field
+                ))),
+            }
+        })
+        .collect()
+}
+
+fn find_note_type(name: &str) -> Result<NoteType, Error> {
+    file::read_note_types()?
+        .into_iter()
+        .find(|note_type| note_type.name() == name)
+        .ok_or_else(|| Error::state(format!("Unknown note type: {}", name)))
+}
+
+fn add_note(note_type: &str, fields: &[(&str, &str)]) -> Result<(), Error> {
+    let note_type = find_note_type(note_type)?;
+    let (note, cards) = build_note(&note_type, fields, &mut IdGenerator::new())?;
+    file::store_notes(&[note])?;
+    storage::open()?.store_cards(&cards)
+}
+
+/// Creates a validated note of `note_type` with `fields` and the cards it
+/// generates, new IDs are taken from `ids`.
+fn build_note(
+    note_type: &NoteType,
+    fields: &[(&str, &str)],
+    ids: &mut IdGenerator,
+) -> Result<(Note, Vec<Card>), Error> {
+    let note_id = ids.next_id();
+
+    let mut note = Note::new(note_id, note_type.name().to_string());
+    for &(name, value) in fields {
+        note.set_field(name, value.to_string());
+    }
+    note_type.validate(&note)?;
+
+    let rendered = note_type.render(&note);
+    if rendered.is_empty() {
+        return Err(Error::state("The note doesn't generate any card."));
+    }
+
+    let cards: Vec<Card> = rendered
+        .into_iter()
+        .map(|(template, question, answer)| {
+            let mut card = Card::new(ids.next_id(), question, answer);
+            card.set_note(note_id, template);
+            card
+        })
+        .collect();
+    Ok((note, cards))
+}
+
+fn edit_note(id: u64, fields: &[(&str, &str)]) -> Result<(), Error> {
+    let mut notes = file::read_notes()?;
+    let note = match notes.iter_mut().find(|note| note.id() == id) {
+        Some(note) => note,
+        None => {
+            return Err(Error::state(format!(
+                "There is no note with ID {}.",
+                Card::serialize_id(id)
+            )))
+        }
     };
+    let note_type = find_note_type(note.note_type())?;
 
-    let cards: Vec<Card> = qa
+    for &(name, value) in fields {
+        note.set_field(name, value.to_string());
+    }
+    note_type.validate(note)?;
+
+    let rendered = note_type.render(note);
+    if rendered.is_empty() {
+        return Err(Error::state("The note doesn't generate any card."));
+    }
+
+    let mut storage = storage::open()?;
+    let mut cards = storage.read_cards()?;
+    let mut ids = IdGenerator::new();
+
+    // Cards of templates which render an empty question (e.g. a removed cloze
+    // deletion) are removed together with their schedule.
+    let removed: Vec<u64> = cards
         .iter()
-        .scan(last_id, |last_id, &(q, a)| {
-            *last_id += 1;
-            Some(Card::new(*last_id, String::from(q), String::from(a)))
+        .filter(|card| match card.note() {
+            Some((note, template)) => {
+                note == id && !rendered.iter().any(|&(other, _, _)| other == template)
+            }
+            None => false,
         })
+        .map(Card::id)
         .collect();
-    file::store_cards(&cards)
+    if !removed.is_empty() {
+        cards.retain(|card| !removed.contains(&card.id()));
+        let calendar = Config::load()?.calendar();
+        let schedule = storage.read_schedule(&calendar)?;
+        let items: Vec<(u64, &ScheduleItem)> = schedule
+            .iter()
+            .filter(|(id, _)| !removed.contains(id))
+            .map(|(id, item)| (*id, item))
+            .collect();
+        storage.write_schedule(&items)?;
+    }
+
+    for (template, question, answer) in rendered {
+        let existing = cards
+            .iter_mut()
+            .find(|card| card.note() == Some((id, template)));
+        match existing {
+            Some(card) => {
+                card.set_question(question);
+                card.set_answer(answer);
+            }
+            None => {
+                let mut card = Card::new(ids.next_id(), question, answer);
+                card.set_note(id, template);
+                cards.push(card);
+            }
+        }
+    }
+
+    storage.backup()?;
+    file::rewrite_notes(&notes)?;
+    storage.rewrite_cards(&cards)
+}
+
+/// Adds `answer` and alternative answers given by options in `matches` which
+/// are not accepted yet to `card`. The hint is set too if the card has none
+/// and media are attached.
+fn merge_answers(card: &mut Card, answer: &str, matches: &ArgMatches) -> Result<(), Error> {
+    let alternatives = matches.values_of("alternative").into_iter().flatten();
+    for answer in iter::once(answer).chain(alternatives) {
+        if !card.is_correct(answer) {
+            card.add_alternative(String::from(answer))?;
+        }
+    }
+    if let (None, Some(hint)) = (card.hint(), matches.value_of("hint")) {
+        card.set_hint(String::from(hint));
+    }
+    add_media(card, matches)
+}
+
+/// Prints groups of cards with the same question separated by empty lines.
+fn dedupe(fuzzy: bool) -> Result<(), Error> {
+    let cards = storage::open()?.read_cards()?;
+    let groups = duplicate::groups(&cards, fuzzy);
+    if groups.is_empty() {
+        println!("No duplicates found.");
+    }
+
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        for card in group {
+            print!("{}", card.to_line());
+        }
+    }
+    Ok(())
+}
+
+/// Stores cards of `(question, answer)` pairs with alternative answers, hint
+/// and media given by options in `matches`. Each question is checked for
+/// duplicates among stored cards and cards added before it; nothing is stored
+/// if a duplicate fails the command. Media files are copied to media
+/// directory.
+fn add_cards(entries: Vec<(String, String)>, matches: &ArgMatches) -> Result<(), Error> {
+    let bidirectional = matches.is_present("bidir");
+    let fuzzy = matches.is_present("fuzzy");
+    let mut storage = storage::open()?;
+    let mut cards = storage.read_cards()?;
+    let stored = cards.len();
+    let mut ids = IdGenerator::new();
+    let note_type = if bidirectional {
+        Some(find_note_type("bidirectional")?)
+    } else {
+        None
+    };
+    let mut notes = Vec::new();
+    let mut merged = false;
+
+    for (question, answer) in entries {
+        let mut questions = vec![question.as_str()];
+        if bidirectional {
+            questions.push(answer.as_str());
+        }
+        let duplicate = questions
+            .iter()
+            .flat_map(|question| duplicate::find(&cards, question, fuzzy))
+            .map(|card| (card.id(), card.question().to_string()))
+            .next();
+        if let Some((id, existing)) = duplicate {
+            match matches.value_of("duplicate").unwrap() {
+                "add" => (),
+                "skip" => {
+                    println!(
+                        "Skipped, card {} asks the same question.",
+                        Card::serialize_id(id)
+                    );
+                    continue;
+                }
+                "merge" if !bidirectional => {
+                    let card = cards.iter_mut().find(|card| card.id() == id).unwrap();
+                    merge_answers(card, &answer, matches)?;
+                    merged = true;
+                    println!("Merged answers into card {}.", Card::serialize_id(id));
+                    continue;
+                }
+                "merge" => {
+                    return Err(Error::state(
+                        "Answers of bidirectional cards can't be merged.",
+                    ))
+                }
+                _ => {
+                    return Err(Error::state(format!(
+                        "Card {} asks the same question: {}\nUse --duplicate to skip, \
+                         merge or add the card.",
+                        Card::serialize_id(id),
+                        existing
+                    )))
+                }
+            }
+        }
+
+        match note_type {
+            Some(ref note_type) => {
+                let fields = [("front", question.as_str()), ("back", answer.as_str())];
+                let (note, note_cards) = build_note(note_type, &fields, &mut ids)?;
+                notes.push(note);
+                cards.extend(note_cards);
+            }
+            None => cards.push(Card::new(ids.next_id(), question, answer)),
+        }
+    }
+
+    if bidirectional {
+        file::store_notes(&notes)?;
+        return storage.store_cards(&cards[stored..]);
+    }
+
+    for card in cards[stored..].iter_mut() {
+        for alternative in matches.values_of("alternative").into_iter().flatten() {
+            card.add_alternative(String::from(alternative))?;
+        }
+        if let Some(hint) = matches.value_of("hint") {
+            card.set_hint(String::from(hint));
+        }
+        add_media(card, matches)?;
+    }
+
+    if merged {
+        storage.rewrite_cards(&cards)
+    } else {
+        storage.store_cards(&cards[stored..])
+    }
+}
+
+/// Copies media files given by options in `matches` to media directory and
+/// attaches those not attached yet to `card`.
+fn add_media(card: &mut Card, matches: &ArgMatches) -> Result<(), Error> {
+    let media_options = [
+        ("audio", MediaKind::Audio, Side::Question),
+        ("image", MediaKind::Image, Side::Question),
+        ("answer_audio", MediaKind::Audio, Side::Answer),
+        ("answer_image", MediaKind::Image, Side::Answer),
+    ];
+    for &(option, kind, side) in &media_options {
+        for path in matches.values_of(option).into_iter().flatten() {
+            let media = Media::new(kind, side, media::import(Path::new(path))?);
+            if !card.media().contains(&media) {
+                card.add_media(media);
+            }
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,200 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Lightweight markup of card content: `**bold**`, `*italics*`, `` `code` ``,
+//! line breaks written as `\n` and list items starting with `- ` on a new
+//! line. Markup characters are escaped with a backslash, e.g. `\*`.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const ITALICS: (&str, &str) = ("\x1b[3m", "\x1b[23m");
+const CODE: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+
+/// How is the markup rendered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    /// Markup is rendered with ANSI escape sequences.
+    Ansi,
+    /// Markup characters are removed and the text is left unformatted.
+    Plain,
+}
+
+impl Style {
+    /// Returns `Ansi` if standard output is a terminal and environment
+    /// variable `NO_COLOR` is not set, `Plain` otherwise.
+    pub fn detect() -> Style {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        if !no_color && io::stdout().is_terminal() {
+            Style::Ansi
+        } else {
+            Style::Plain
+        }
+    }
+}
+
+/// Renders marked up `text`. The result may consist of multiple lines.
+pub fn render(text: &str, style: Style) -> String {
+    split_lines(text)
+        .iter()
+        .map(|line| render_line(line, style))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Split text at line breaks, i.e. at `\n` not preceded by another
+/// backslash.
+fn split_lines(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        let line = lines.last_mut().unwrap();
+        if c != '\\' {
+            line.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => lines.push(String::new()),
+            Some(escaped) => {
+                line.push(c);
+                line.push(escaped);
+            }
+            None => line.push(c),
+        }
+    }
+
+    lines
+}
+
+fn render_line(line: &str, style: Style) -> String {
+    match line.strip_prefix("- ") {
+        Some(item) => {
+            let bullet = match style {
+                Style::Ansi => "• ",
+                Style::Plain => "- ",
+            };
+            format!("{}{}", bullet, render_inline(item, style))
+        }
+        None => render_inline(line, style),
+    }
+}
+
+fn render_inline(line: &str, style: Style) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut rendered = String::new();
+    let mut bold = false;
+    let mut italics = false;
+
+    let push_escape = |rendered: &mut String, code: (&str, &str), start: bool| {
+        if style == Style::Ansi {
+            rendered.push_str(if start { code.0 } else { code.1 });
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if c == '\\' && (next == Some('*') || next == Some('`') || next == Some('\\')) {
+            rendered.push(next.unwrap());
+            i += 2;
+        } else if c == '`' && find(&chars, i + 1, "`").is_some() {
+            let end = find(&chars, i + 1, "`").unwrap();
+            push_escape(&mut rendered, CODE, true);
+            rendered.extend(&chars[i + 1..end]);
+            push_escape(&mut rendered, CODE, false);
+            i = end + 1;
+        } else if c == '*' && next == Some('*') && (bold || opens(&chars, i + 2, "**")) {
+            push_escape(&mut rendered, BOLD, !bold);
+            bold = !bold;
+            i += 2;
+        } else if c == '*' && (italics || opens(&chars, i + 1, "*")) {
+            push_escape(&mut rendered, ITALICS, !italics);
+            italics = !italics;
+            i += 1;
+        } else {
+            rendered.push(c);
+            i += 1;
+        }
+    }
+
+    rendered
+}
+
+/// Returns whether a marker before `start` opens a span, i.e. it is followed
+/// by a non-white space character and closed later on the line.
+fn opens(chars: &[char], start: usize, marker: &str) -> bool {
+    match chars.get(start) {
+        Some(c) if !c.is_whitespace() => find(chars, start + 1, marker).is_some(),
+        _ => false,
+    }
+}
+
+/// Returns index of the first unescaped occurrence of `marker` at or after
+/// `start`.
+fn find(chars: &[char], start: usize, marker: &str) -> Option<usize> {
+    let marker: Vec<char> = marker.chars().collect();
+    let mut i = start;
+    while i + marker.len() <= chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if chars[i..i + marker.len()] == marker[..] {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_plain() {
+        assert_eq!(render("plain text", Style::Plain), "plain text");
+        assert_eq!(
+            render("**der** Hund, *pl.* `Hunde`", Style::Plain),
+            "der Hund, pl. Hunde"
+        );
+        assert_eq!(
+            render("animals:\\n- dog\\n- cat", Style::Plain),
+            "animals:\n- dog\n- cat"
+        );
+        assert_eq!(render("5 * 3 = 15", Style::Plain), "5 * 3 = 15");
+        assert_eq!(render("\\*not\\* italics", Style::Plain), "*not* italics");
+        assert_eq!(render("C:\\\\new", Style::Plain), "C:\\new");
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        assert_eq!(
+            render("**der** Hund, *pl.* `Hunde`", Style::Ansi),
+            "\x1b[1mder\x1b[22m Hund, \x1b[3mpl.\x1b[23m \x1b[36mHunde\x1b[39m"
+        );
+        assert_eq!(
+            render("- **a** `*b*`", Style::Ansi),
+            "• \x1b[1ma\x1b[22m \x1b[36m*b*\x1b[39m"
+        );
+    }
+}
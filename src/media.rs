@@ -0,0 +1,168 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error;
+use crate::file::get_vole_dir;
+use crate::hook::run_command;
+use std::fs::{self, create_dir};
+use std::path::{Path, PathBuf};
+
+/// Name of the directory inside VoLe directory where media files are stored.
+const MEDIA_DIR_NAME: &str = "media";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MediaKind {
+    Audio,
+    Image,
+}
+
+/// Side of a card, media of a side are played when the side is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Question,
+    Answer,
+}
+
+/// A media file attached to a card. The file is stored in media directory
+/// and referenced by its name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Media {
+    kind: MediaKind,
+    side: Side,
+    file: String,
+}
+
+impl Media {
+    pub fn new(kind: MediaKind, side: Side, file: String) -> Media {
+        Media { kind, side, file }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        self.kind
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Name of the file inside media directory.
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Name of the card attribute storing media of kind `kind` on side
+    /// `side`.
+    pub fn attribute(kind: MediaKind, side: Side) -> &'static str {
+        match (side, kind) {
+            (Side::Question, MediaKind::Audio) => "audio",
+            (Side::Question, MediaKind::Image) => "image",
+            (Side::Answer, MediaKind::Audio) => "answer_audio",
+            (Side::Answer, MediaKind::Image) => "answer_image",
+        }
+    }
+
+    /// Inverse of `attribute`, returns `None` for other attributes.
+    pub fn parse_attribute(name: &str) -> Option<(MediaKind, Side)> {
+        match name {
+            "audio" => Some((MediaKind::Audio, Side::Question)),
+            "image" => Some((MediaKind::Image, Side::Question)),
+            "answer_audio" => Some((MediaKind::Audio, Side::Answer)),
+            "answer_image" => Some((MediaKind::Image, Side::Answer)),
+            _ => None,
+        }
+    }
+
+    /// Runs command `command` with `{file}` replaced by path of the media
+    /// file, e.g. `mpv {file}`. The command is run directly (not by a shell)
+    /// and this waits until it finishes.
+    pub fn play(&self, command: &str) -> Result<(), Error> {
+        let path = get_media_dir()?.join(&self.file);
+        run_command(command, &[("{file}", &path.to_string_lossy())])
+    }
+}
+
+/// Copies file at `path` to media directory and returns its name there. A
+/// numeric suffix is added to the name if a different file with the same name
+/// is already stored.
+pub fn import(path: &Path) -> Result<String, Error> {
+    let content = fs::read(path).map_err(|error| Error::io("read file", path, error))?;
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => {
+            let reason = format!("Not a file: {}", path.to_string_lossy());
+            return Err(Error::state(reason));
+        }
+    };
+
+    let media_dir = get_media_dir()?;
+    let mut candidate = name.clone();
+    for i in 1.. {
+        let target = media_dir.join(&candidate);
+        if !target.exists() {
+            fs::write(&target, &content)
+                .map_err(|error| Error::io("write file", &target, error))?;
+            break;
+        }
+        if fs::read(&target).ok().as_ref() == Some(&content) {
+            break;
+        }
+        candidate = suffixed(&name, i);
+    }
+
+    Ok(candidate)
+}
+
+/// Adds `-<number>` before extension of file name `name`.
+fn suffixed(name: &str, number: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..dot], number, &name[dot..]),
+        _ => format!("{}-{}", name, number),
+    }
+}
+
+/// Returns path to media directory and creates it if necessary.
+pub fn get_media_dir() -> Result<PathBuf, Error> {
+    let mut path = get_vole_dir()?;
+    path.push(MEDIA_DIR_NAME);
+    if !path.exists() {
+        create_dir(&path).map_err(|error| Error::io("create directory", &path, error))?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attribute() {
+        for &kind in &[MediaKind::Audio, MediaKind::Image] {
+            for &side in &[Side::Question, Side::Answer] {
+                let attribute = Media::attribute(kind, side);
+                assert_eq!(Media::parse_attribute(attribute), Some((kind, side)));
+            }
+        }
+        assert_eq!(Media::parse_attribute("hint"), None);
+    }
+
+    #[test]
+    fn test_suffixed() {
+        assert_eq!(suffixed("hund.mp3", 1), "hund-1.mp3");
+        assert_eq!(suffixed("hund", 2), "hund-2");
+        assert_eq!(suffixed(".hidden", 3), ".hidden-3");
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::calendar::Calendar;
+use crate::card::Card;
+use crate::error::Error;
+use crate::file::{rewrite_lines, Format, CARDS_FORMAT, REVIEWS_FORMAT, SCHEDULE_FORMAT};
+use crate::note::Note;
+use crate::progress::Progress;
+use crate::review::Review;
+use crate::scheduler::ScheduleItem;
+use fnv::FnvHashSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::iter;
+use std::path::Path;
+
+/// Records of a file keyed (and thus sorted) by their IDs.
+type Records<T> = BTreeMap<u64, T>;
+
+/// Three-way merge of VoLe file `ours` with file `theirs`, both derived from
+/// file `base`, e.g. by git. The result is written to `ours`. Kind of the
+/// files is given by file name of `path`: `cards.txt`, `notes.txt`,
+/// `schedule.txt`, `reviews.txt` or `progress.txt`.
+///
+/// Records (e.g. cards) are merged one by one by their IDs and cards and
+/// schedule are written in the current format version. A schedule item
+/// changed on both sides is taken from the side where the card was revisited
+/// later and progress of the later day wins. Reviews of both sides are kept.
+/// Cards and notes changed differently on both sides are conflicts; our
+/// version is kept and IDs of the conflicting records are returned.
+pub fn merge_files(
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    path: &Path,
+    calendar: &Calendar,
+) -> Result<Vec<u64>, Error> {
+    let name = path.file_name().map(|name| name.to_string_lossy());
+    let (lines, conflicts): (Vec<String>, Vec<u64>) = match name.as_ref().map(|name| name.as_ref())
+    {
+        Some("cards.txt") => {
+            let parse = |path| read_records(path, Some(CARDS_FORMAT), Card::from_line, Card::id);
+            let (cards, conflicts) = merge(parse(base)?, parse(ours)?, parse(theirs)?, None);
+            let lines = iter::once(CARDS_FORMAT.header())
+                .chain(cards.values().map(Card::to_line))
+                .collect();
+            (lines, conflicts)
+        }
+        Some("notes.txt") => {
+            let parse = |path| read_records(path, None, Note::from_line, Note::id);
+            let (notes, conflicts) = merge(parse(base)?, parse(ours)?, parse(theirs)?, None);
+            (notes.values().map(Note::to_line).collect(), conflicts)
+        }
+        Some("schedule.txt") => {
+            let parse = |path| {
+                read_records(
+                    path,
+                    Some(SCHEDULE_FORMAT),
+                    |line| ScheduleItem::deserialize(line, calendar),
+                    |&(id, _)| id,
+                )
+            };
+            let (items, conflicts) = merge(
+                parse(base)?,
+                parse(ours)?,
+                parse(theirs)?,
+                Some(later_revisited),
+            );
+            let lines = iter::once(SCHEDULE_FORMAT.header())
+                .chain(items.values().map(|(id, item)| item.serialize(*id)))
+                .collect();
+            (lines, conflicts)
+        }
+        Some("reviews.txt") => {
+            let reviews = merge_reviews(read_reviews(ours)?, read_reviews(theirs)?);
+            let lines = iter::once(REVIEWS_FORMAT.header())
+                .chain(reviews.iter().map(Review::serialize))
+                .collect();
+            (lines, Vec::new())
+        }
+        Some("progress.txt") => {
+            let progress = [read_progress(ours)?, read_progress(theirs)?]
+                .iter()
+                .flatten()
+                .max_by_key(|progress| (progress.date(), progress.new_cards() + progress.reviews()))
+                .map(Progress::serialize);
+            (progress.into_iter().collect(), Vec::new())
+        }
+        _ => {
+            let reason = format!("Not a mergeable VoLe file: {}", path.to_string_lossy());
+            return Err(Error::state(reason));
+        }
+    };
+
+    rewrite_lines(ours, &lines)?;
+    Ok(conflicts)
+}
+
+/// Three-way merge of records. A record changed only on one side is taken
+/// from that side. A record changed on both sides is resolved with `resolve`
+/// if given, otherwise our version is kept and the ID is returned as a
+/// conflict.
+fn merge<T: PartialEq>(
+    mut base: Records<T>,
+    mut ours: Records<T>,
+    mut theirs: Records<T>,
+    resolve: Option<fn(T, T) -> T>,
+) -> (Records<T>, Vec<u64>) {
+    let ids: BTreeSet<u64> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect();
+
+    let mut merged = Records::new();
+    let mut conflicts = Vec::new();
+    for id in ids {
+        let base = base.remove(&id);
+        let ours = ours.remove(&id);
+        let theirs = theirs.remove(&id);
+
+        let record = if ours == theirs || theirs == base {
+            ours
+        } else if ours == base {
+            theirs
+        } else {
+            match (ours, theirs, resolve) {
+                (Some(ours), Some(theirs), Some(resolve)) => Some(resolve(ours, theirs)),
+                (ours, theirs, _) => {
+                    conflicts.push(id);
+                    ours.or(theirs)
+                }
+            }
+        };
+
+        if let Some(record) = record {
+            merged.insert(id, record);
+        }
+    }
+
+    (merged, conflicts)
+}
+
+/// Merges logs of reviews appended on both sides: our reviews followed by
+/// their reviews missing in ours.
+fn merge_reviews(mut ours: Vec<Review>, theirs: Vec<Review>) -> Vec<Review> {
+    let reviews: FnvHashSet<Review> = ours.iter().cloned().collect();
+    ours.extend(
+        theirs
+            .into_iter()
+            .filter(|review| !reviews.contains(review)),
+    );
+    ours
+}
+
+/// Returns the schedule item of the two whose card was revisited later.
+fn later_revisited(ours: (u64, ScheduleItem), theirs: (u64, ScheduleItem)) -> (u64, ScheduleItem) {
+    if theirs.1.last_revisit() > ours.1.last_revisit() {
+        theirs
+    } else {
+        ours
+    }
+}
+
+/// Reads records of file at `path`. The file may start with a header of
+/// `format`.
+fn read_records<T, P, I>(
+    path: &Path,
+    format: Option<Format>,
+    parse: P,
+    id: I,
+) -> Result<Records<T>, Error>
+where
+    P: Fn(&str) -> Result<T, Error>,
+    I: Fn(&T) -> u64,
+{
+    let content = read_to_string(path).map_err(|error| Error::io("read file", path, error))?;
+    let lines = match format {
+        Some(format) => format.records(&content)?,
+        None => content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .collect(),
+    };
+    lines
+        .into_iter()
+        .map(|(number, line)| {
+            let record = parse(line).map_err(|error| error.at_line(number))?;
+            Ok((id(&record), record))
+        })
+        .collect()
+}
+
+fn read_reviews(path: &Path) -> Result<Vec<Review>, Error> {
+    let content = read_to_string(path).map_err(|error| Error::io("read file", path, error))?;
+    REVIEWS_FORMAT
+        .records(&content)?
+        .into_iter()
+        .map(|(number, line)| Review::deserialize(line).map_err(|error| error.at_line(number)))
+        .collect()
+}
+
+fn read_progress(path: &Path) -> Result<Option<Progress>, Error> {
+    let content = read_to_string(path).map_err(|error| Error::io("read file", path, error))?;
+    match content.trim() {
+        "" => Ok(None),
+        line => Progress::deserialize(line).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn records(cards: &[(u64, &str)]) -> Records<String> {
+        cards
+            .iter()
+            .map(|&(id, answer)| (id, answer.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = records(&[(1, "dog"), (2, "cat"), (3, "mouse"), (4, "fish")]);
+        let ours = records(&[(1, "hound"), (2, "cat"), (3, "rat"), (5, "bird")]);
+        let theirs = records(&[(1, "dog"), (2, "kitten"), (3, "vole"), (4, "fish")]);
+
+        let (merged, conflicts) = merge(base.clone(), ours.clone(), theirs.clone(), None);
+        assert_eq!(
+            merged,
+            records(&[(1, "hound"), (2, "kitten"), (3, "rat"), (5, "bird")])
+        );
+        assert_eq!(conflicts, vec![3]);
+
+        let resolve: fn(String, String) -> String = |ours, theirs| ours.max(theirs);
+        let (merged, conflicts) = merge(base, ours, theirs, Some(resolve));
+        assert_eq!(merged[&3], "vole");
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_reviews() {
+        let review =
+            |card, minute| Review::new(card, Utc.ymd(2019, 3, 10).and_hms(8, minute, 0), 4);
+        let ours = vec![review(1, 0), review(2, 1)];
+        let theirs = vec![review(1, 0), review(3, 2)];
+        assert_eq!(
+            merge_reviews(ours, theirs),
+            vec![review(1, 0), review(2, 1), review(3, 2)]
+        );
+    }
+}
@@ -0,0 +1,618 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::card::Card;
+use crate::error::Error;
+use fnv::FnvHashMap;
+
+/// A unit of information (e.g. a word with its translation and an example
+/// sentence) consisting of named fields. One or more cards are generated from
+/// a note by templates of its `NoteType`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    id: u64,
+    note_type: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Note {
+    pub fn new(id: u64, note_type: String) -> Note {
+        Note {
+            id,
+            note_type,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Name of the `NoteType` of the note.
+    pub fn note_type(&self) -> &str {
+        &self.note_type
+    }
+
+    /// Returns value of field `name` or `None` if the field is not set.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Sets value of field `name`, replacing its previous value.
+    pub fn set_field(&mut self, name: &str, value: String) {
+        match self.fields.iter_mut().find(|(field, _)| field == name) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((name.to_string(), value)),
+        }
+    }
+
+    /// Serialize `Note` to a `String` of a single line; with line-feed at the
+    /// end. The line consists of TAB separated ID, note type and `name=value`
+    /// fields.
+    pub fn to_line(&self) -> String {
+        let mut line = format!("{}\t{}", Card::serialize_id(self.id), self.note_type);
+        for (name, value) in &self.fields {
+            line.push_str(&format!("\t{}={}", name, value));
+        }
+        line.push('\n');
+        line
+    }
+
+    /// Parse `Note` from a `&str` of a single line (ending with line-feed).
+    pub fn from_line(line: &str) -> Result<Note, Error> {
+        let parts: Vec<&str> = line
+            .trim_end_matches(&['\r', '\n'][..])
+            .split('\t')
+            .collect();
+
+        if parts.len() < 2 {
+            let reason = format!("Expected at least two TAB separated tokens, got: {}", line);
+            return Err(Error::parse(reason));
+        }
+
+        let id = Card::parse_id(parts[0])?;
+        let mut note = Note::new(id, parts[1].to_string());
+        for field in &parts[2..] {
+            let mut field_parts = field.splitn(2, '=');
+            let name = field_parts.next().unwrap();
+            match field_parts.next() {
+                Some(value) => note.set_field(name, value.to_string()),
+                None => {
+                    let reason = format!("Expected name=value field, got: {}", field);
+                    return Err(Error::parse(reason));
+                }
+            }
+        }
+
+        Ok(note)
+    }
+}
+
+/// A pair of question and answer templates. All occurrences of `{{name}}` are
+/// replaced with value of field `name` of a note.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    question: String,
+    answer: String,
+}
+
+impl Template {
+    pub fn new(question: String, answer: String) -> Template {
+        Template { question, answer }
+    }
+
+    /// Renders question and answer of a card from note `note`.
+    pub fn render(&self, note: &Note) -> (String, String) {
+        (render(&self.question, note), render(&self.answer, note))
+    }
+}
+
+fn render(template: &str, note: &Note) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        rendered.push_str(note.field(name).unwrap_or(""));
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Returns sorted numbers of all cloze deletions (e.g. `{{c1::habe}}`) in
+/// `text`.
+fn cloze_numbers(text: &str) -> Vec<usize> {
+    let mut numbers = Vec::new();
+    for_each_cloze(text, |_, number| {
+        numbers.push(number);
+        None
+    });
+    numbers.sort();
+    numbers.dedup();
+    numbers
+}
+
+/// Renders `text` with cloze deletion `hidden` replaced by `[...]` (or by
+/// `[hint]` if the deletion is written as `{{c1::habe::hint}}`). All other
+/// deletions are replaced by their content.
+fn render_cloze(text: &str, hidden: Option<usize>) -> String {
+    for_each_cloze(text, |content, number| {
+        let mut parts = content.splitn(2, "::");
+        let answer = parts.next().unwrap();
+        if hidden != Some(number) {
+            return Some(answer.to_string());
+        }
+        match parts.next() {
+            Some(hint) => Some(format!("[{}]", hint)),
+            None => Some("[...]".to_string()),
+        }
+    })
+}
+
+/// Calls `f` with content and number of each cloze deletion in `text`.
+/// Returns `text` with deletions replaced by values returned from `f`.
+fn for_each_cloze<F>(text: &str, mut f: F) -> String
+where
+    F: FnMut(&str, usize) -> Option<String>,
+{
+    let mut rendered = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{c") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let deletion = &rest[start + 3..end];
+        let mut parts = deletion.splitn(2, "::");
+        let number = parts.next().unwrap().parse::<usize>().ok();
+        match (number, parts.next()) {
+            (Some(number), Some(content)) if number > 0 => {
+                rendered.push_str(&rest[..start]);
+                if let Some(replacement) = f(content, number) {
+                    rendered.push_str(&replacement);
+                }
+            }
+            _ => rendered.push_str(&rest[..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Kind of notes given by a list of fields and card templates.
+///
+/// A cloze note type generates one card per cloze deletion in its cloze
+/// field rather than one card per template. All cards are rendered by the
+/// first template with the cloze field substituted by the text with the
+/// deletion hidden in the question and by the full text in the answer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteType {
+    name: String,
+    fields: Vec<String>,
+    templates: Vec<Template>,
+    cloze: Option<String>,
+}
+
+impl NoteType {
+    pub fn new(name: String, fields: Vec<String>, templates: Vec<Template>) -> NoteType {
+        NoteType {
+            name,
+            fields,
+            templates,
+            cloze: None,
+        }
+    }
+
+    /// Note type with fields `front` and `back` generating a single card.
+    pub fn basic() -> NoteType {
+        NoteType::new(
+            "basic".to_string(),
+            vec!["front".to_string(), "back".to_string()],
+            vec![Template::new(
+                "{{front}}".to_string(),
+                "{{back}}".to_string(),
+            )],
+        )
+    }
+
+    /// Note type with fields `front` and `back` generating two cards with
+    /// question and answer swapped.
+    pub fn bidirectional() -> NoteType {
+        NoteType::new(
+            "bidirectional".to_string(),
+            vec!["front".to_string(), "back".to_string()],
+            vec![
+                Template::new("{{front}}".to_string(), "{{back}}".to_string()),
+                Template::new("{{back}}".to_string(), "{{front}}".to_string()),
+            ],
+        )
+    }
+
+    /// Note type with field `text` generating a card for each cloze deletion
+    /// (e.g. `Ich {{c1::habe}} Hunger`) in the text.
+    pub fn cloze() -> NoteType {
+        NoteType {
+            cloze: Some("text".to_string()),
+            ..NoteType::new(
+                "cloze".to_string(),
+                vec!["text".to_string()],
+                vec![Template::new(
+                    "{{text}}".to_string(),
+                    "{{text}}".to_string(),
+                )],
+            )
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub fn templates(&self) -> &[Template] {
+        &self.templates
+    }
+
+    /// Check that `note` sets only fields of this note type and that their
+    /// values contain no TAB or line breaks.
+    pub fn validate(&self, note: &Note) -> Result<(), Error> {
+        for (name, value) in note.fields() {
+            if !self.fields.contains(name) {
+                return Err(Error::state(format!(
+                    "Note type \"{}\" has no field \"{}\".",
+                    self.name, name
+                )));
+            }
+            // Both would break lines of notes and cards files.
+            if value.contains(&['\t', '\n', '\r'][..]) {
+                return Err(Error::state(format!(
+                    "Field \"{}\" can't contain TAB or line break characters.",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the field with cloze deletions, `None` if this is not a cloze
+    /// note type.
+    pub fn cloze_field(&self) -> Option<&str> {
+        self.cloze.as_deref()
+    }
+
+    /// Renders questions and answers of all cards of note `note` together
+    /// with indices of templates they were generated by. Templates rendered
+    /// to an empty question are skipped.
+    ///
+    /// Cards of cloze note types are indexed by cloze deletion number minus
+    /// one so that a card keeps its index when the text is edited.
+    pub fn render(&self, note: &Note) -> Vec<(usize, String, String)> {
+        if let Some(field) = self.cloze_field() {
+            return self.render_cloze(field, note);
+        }
+
+        self.templates
+            .iter()
+            .enumerate()
+            .map(|(index, template)| {
+                let (question, answer) = template.render(note);
+                (index, question, answer)
+            })
+            .filter(|(_, question, _)| !question.trim().is_empty())
+            .collect()
+    }
+
+    fn render_cloze(&self, field: &str, note: &Note) -> Vec<(usize, String, String)> {
+        let template = match self.templates.first() {
+            Some(template) => template,
+            None => return Vec::new(),
+        };
+        let text = note.field(field).unwrap_or("");
+
+        let mut answer_note = note.clone();
+        answer_note.set_field(field, render_cloze(text, None));
+        let answer = render(&template.answer, &answer_note);
+
+        cloze_numbers(text)
+            .into_iter()
+            .map(|number| {
+                let mut question_note = note.clone();
+                question_note.set_field(field, render_cloze(text, Some(number)));
+                let question = render(&template.question, &question_note);
+                (number - 1, question, answer.clone())
+            })
+            .collect()
+    }
+
+    /// Parse user defined note types. Each note type starts with its name in
+    /// square brackets followed by a whitespace separated list of fields and
+    /// any number of question and answer templates:
+    ///
+    /// ```text
+    /// [vocabulary]
+    /// fields = word translation example
+    /// question = {{word}}
+    /// answer = {{translation}} ({{example}})
+    /// question = {{translation}}
+    /// answer = {{word}}
+    /// ```
+    ///
+    /// Option `cloze = <field>` makes the note type a cloze note type with
+    /// deletions in field `<field>`.
+    pub fn parse(content: &str) -> Result<Vec<NoteType>, Error> {
+        let mut note_types: Vec<NoteType> = Vec::new();
+        let mut question: Option<String> = None;
+
+        for (i, line) in content.lines().enumerate() {
+            let error = |reason: String| Error::parse(reason).at_line(i + 1);
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                if question.is_some() {
+                    return Err(error("Expected answer template.".to_string()));
+                }
+                let name = line[1..line.len() - 1].trim().to_string();
+                note_types.push(NoteType::new(name, Vec::new(), Vec::new()));
+                continue;
+            }
+
+            let note_type = match note_types.last_mut() {
+                Some(note_type) => note_type,
+                None => return Err(error(format!("Expected [note type], got: {}", line))),
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = match parts.next() {
+                Some(value) => value.trim().to_string(),
+                None => return Err(error(format!("Expected \"key = value\", got: {}", line))),
+            };
+
+            match (key, question.take()) {
+                ("fields", None) => {
+                    note_type.fields = value.split_whitespace().map(String::from).collect();
+                }
+                ("cloze", None) => note_type.cloze = Some(value),
+                ("question", None) => question = Some(value),
+                ("answer", Some(question)) => {
+                    note_type.templates.push(Template::new(question, value));
+                }
+                ("answer", None) => {
+                    return Err(error("Expected question template.".to_string()));
+                }
+                (_, Some(_)) => return Err(error("Expected answer template.".to_string())),
+                (key, None) => return Err(error(format!("Unknown option: {}", key))),
+            }
+        }
+
+        if question.is_some() {
+            return Err(Error::parse("Missing answer template at the end of file."));
+        }
+
+        for note_type in &mut note_types {
+            if let Some(field) = note_type.cloze.as_ref() {
+                if !note_type.fields.contains(field) {
+                    return Err(Error::parse(format!(
+                        "Note type \"{}\" has no field \"{}\".",
+                        note_type.name, field
+                    )));
+                }
+                if note_type.templates.is_empty() {
+                    let template = format!("{{{{{}}}}}", field);
+                    note_type
+                        .templates
+                        .push(Template::new(template.clone(), template));
+                }
+            }
+        }
+
+        Ok(note_types)
+    }
+}
+
+/// Links cards generated from the same note as siblings of each other.
+pub fn link_siblings(cards: &mut [Card]) {
+    let mut notes: FnvHashMap<u64, Vec<u64>> = FnvHashMap::default();
+    for card in cards.iter() {
+        if let Some((note, _)) = card.note() {
+            notes.entry(note).or_default().push(card.id());
+        }
+    }
+
+    for card in cards.iter_mut() {
+        let note = match card.note() {
+            Some((note, _)) => note,
+            None => continue,
+        };
+        let id = card.id();
+        for &sibling in notes[&note].iter().filter(|&&sibling| sibling != id) {
+            card.add_sibling(sibling);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_note_line() {
+        let line = "000000000000000a\tvocabulary\tword=Hund\ttranslation=dog = pes\n";
+        let note = Note::from_line(line).unwrap();
+        assert_eq!(note.id(), 10);
+        assert_eq!(note.note_type(), "vocabulary");
+        assert_eq!(note.field("word"), Some("Hund"));
+        assert_eq!(note.field("translation"), Some("dog = pes"));
+        assert_eq!(note.field("gender"), None);
+        assert_eq!(note.to_line(), line);
+
+        assert_eq!(
+            Note::from_line("000000000000000a\tbasic\tfront\n")
+                .unwrap_err()
+                .to_string(),
+            "Expected name=value field, got: front"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let mut note = Note::new(1, "bidirectional".to_string());
+        note.set_field("front", "Hund".to_string());
+        note.set_field("back", "dog".to_string());
+
+        let cards = NoteType::bidirectional().render(&note);
+        assert_eq!(
+            cards,
+            vec![
+                (0, "Hund".to_string(), "dog".to_string()),
+                (1, "dog".to_string(), "Hund".to_string()),
+            ]
+        );
+
+        let template = Template::new("{{ front }} {{gender}}".to_string(), "{{back".to_string());
+        assert_eq!(
+            template.render(&note),
+            ("Hund ".to_string(), "{{back".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_cloze() {
+        let mut note = Note::new(1, "cloze".to_string());
+        note.set_field(
+            "text",
+            "{{c2::Ich}} {{c1::habe}} {{c3::Hunger::noun}}, {{c2::ich}} {{c::x}}".to_string(),
+        );
+
+        let full = "Ich habe Hunger, ich {{c::x}}".to_string();
+        assert_eq!(
+            NoteType::cloze().render(&note),
+            vec![
+                (
+                    0,
+                    "Ich [...] Hunger, ich {{c::x}}".to_string(),
+                    full.clone()
+                ),
+                (
+                    1,
+                    "[...] habe Hunger, [...] {{c::x}}".to_string(),
+                    full.clone()
+                ),
+                (2, "Ich habe [noun], ich {{c::x}}".to_string(), full),
+            ]
+        );
+
+        note.set_field("text", "Ich habe Hunger".to_string());
+        assert!(NoteType::cloze().render(&note).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut note = Note::new(1, "basic".to_string());
+        note.set_field("front", "Hund".to_string());
+        assert!(NoteType::basic().validate(&note).is_ok());
+
+        note.set_field("back", "dog\tpes".to_string());
+        assert_eq!(
+            NoteType::basic().validate(&note).unwrap_err().to_string(),
+            "Field \"back\" can't contain TAB or line break characters."
+        );
+        note.set_field("gender", "m".to_string());
+        assert!(NoteType::cloze().validate(&note).is_err());
+    }
+
+    #[test]
+    fn test_parse_note_types() {
+        let content = "# My types\n\
+                       [vocabulary]\n\
+                       fields = word translation gender\n\
+                       question = {{word}}\n\
+                       answer = {{translation}} ({{gender}})\n\
+                       question = {{translation}}\n\
+                       answer = {{word}}\n";
+        let note_types = NoteType::parse(content).unwrap();
+        assert_eq!(note_types.len(), 1);
+        let note_type = &note_types[0];
+        assert_eq!(note_type.name(), "vocabulary");
+        assert_eq!(note_type.fields(), &["word", "translation", "gender"]);
+        assert_eq!(note_type.templates().len(), 2);
+
+        let mut note = Note::new(1, "vocabulary".to_string());
+        note.set_field("word", "Hund".to_string());
+        note.set_field("translation", "dog".to_string());
+        note.set_field("gender", "m".to_string());
+        assert!(note_type.validate(&note).is_ok());
+        assert_eq!(
+            note_type.render(&note)[0],
+            (0, "Hund".to_string(), "dog (m)".to_string())
+        );
+
+        note.set_field("plural", "Hunde".to_string());
+        assert_eq!(
+            note_type.validate(&note).unwrap_err().to_string(),
+            "Note type \"vocabulary\" has no field \"plural\"."
+        );
+
+        let note_types =
+            NoteType::parse("[sentence]\nfields = text translation\ncloze = text").unwrap();
+        assert_eq!(note_types[0].cloze_field(), Some("text"));
+        assert_eq!(note_types[0].templates().len(), 1);
+        assert_eq!(
+            NoteType::parse("[sentence]\nfields = text\ncloze = sentence")
+                .unwrap_err()
+                .to_string(),
+            "Note type \"sentence\" has no field \"sentence\"."
+        );
+
+        assert_eq!(
+            NoteType::parse("fields = a b").unwrap_err().to_string(),
+            "Error on line 1: Expected [note type], got: fields = a b"
+        );
+        assert_eq!(
+            NoteType::parse("[a]\nquestion = {{a}}\nquestion = {{b}}")
+                .unwrap_err()
+                .to_string(),
+            "Error on line 3: Expected answer template."
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error;
+use crate::storage::Storage;
+use chrono::NaiveDate;
+
+/// Number of new cards introduced and cards reviewed during a single day
+/// (possibly over several learning sessions).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    date: NaiveDate,
+    new_cards: usize,
+    reviews: usize,
+}
+
+impl Progress {
+    /// Zero progress of day `date`.
+    pub fn new(date: NaiveDate) -> Progress {
+        Progress {
+            date,
+            new_cards: 0,
+            reviews: 0,
+        }
+    }
+
+    /// Progress of day `date` with given numbers of new cards and reviews.
+    pub fn from_counts(date: NaiveDate, new_cards: usize, reviews: usize) -> Progress {
+        Progress {
+            date,
+            new_cards,
+            reviews,
+        }
+    }
+
+    /// Load progress of day `date` from `storage`. Progress stored for any
+    /// other day is ignored and zero progress is returned instead.
+    pub fn load(storage: &dyn Storage, date: NaiveDate) -> Result<Progress, Error> {
+        match storage.read_progress()? {
+            Some(progress) if progress.date == date => Ok(progress),
+            _ => Ok(Progress::new(date)),
+        }
+    }
+
+    /// Saves progress to `storage` replacing previously stored progress.
+    pub fn save(&self, storage: &mut dyn Storage) -> Result<(), Error> {
+        storage.write_progress(self)
+    }
+
+    /// Serialize progress to a line (ending with line-feed) of TAB separated
+    /// date, number of new cards and number of reviews.
+    pub fn serialize(&self) -> String {
+        format!(
+            "{}\t{}\t{}\n",
+            self.date.format("%Y-%m-%d"),
+            self.new_cards,
+            self.reviews
+        )
+    }
+
+    pub fn deserialize(line: &str) -> Result<Progress, Error> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 3 {
+            let reason = format!("Expected three TAB separated tokens, got: {}", line);
+            return Err(Error::parse(reason));
+        }
+
+        let date = NaiveDate::parse_from_str(parts[0], "%Y-%m-%d")
+            .map_err(|reason| Error::field_source("date", reason))?;
+        let new_cards: usize = parts[1]
+            .parse()
+            .map_err(|reason| Error::field_source("new cards count", reason))?;
+        let reviews: usize = parts[2]
+            .parse()
+            .map_err(|reason| Error::field_source("reviews count", reason))?;
+
+        Ok(Progress {
+            date,
+            new_cards,
+            reviews,
+        })
+    }
+
+    /// The day of the progress.
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    /// Number of new cards introduced during the day.
+    pub fn new_cards(&self) -> usize {
+        self.new_cards
+    }
+
+    /// Number of due cards reviewed during the day.
+    pub fn reviews(&self) -> usize {
+        self.reviews
+    }
+
+    pub fn add_new_card(&mut self) {
+        self.new_cards += 1;
+    }
+
+    pub fn add_review(&mut self) {
+        self.reviews += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_serialization() {
+        let mut progress = Progress::new(NaiveDate::from_ymd(2019, 3, 10));
+        progress.add_new_card();
+        progress.add_review();
+        progress.add_review();
+
+        assert_eq!(progress.serialize(), "2019-03-10\t1\t2\n");
+        assert_eq!(Progress::deserialize("2019-03-10\t1\t2").unwrap(), progress);
+        assert!(Progress::deserialize("2019-03-10\t1").is_err());
+        assert!(Progress::deserialize("2019-03-10\tmany\t2").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let today = NaiveDate::from_ymd(2019, 3, 10);
+        let mut storage = MemoryStorage::new();
+        assert_eq!(
+            Progress::load(&storage, today).unwrap(),
+            Progress::new(today)
+        );
+
+        let mut progress = Progress::new(today);
+        progress.add_new_card();
+        progress.save(&mut storage).unwrap();
+        assert_eq!(Progress::load(&storage, today).unwrap(), progress);
+
+        // Progress of previous days doesn't count.
+        let tomorrow = today.succ();
+        assert_eq!(
+            Progress::load(&storage, tomorrow).unwrap(),
+            Progress::new(tomorrow)
+        );
+    }
+}
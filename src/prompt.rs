@@ -105,12 +105,26 @@ enum ParsingResult<T> {
     Err,
 }
 
+/// Print question to standard output and read a line of free text from
+/// standard input. The returned text is without the trailing line-feed.
+pub fn read_text(question: &str) -> String {
+    let mut out = io::stdout();
+    out.write_all(question.as_bytes()).unwrap();
+    out.write_all(b": ").unwrap();
+    out.flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    input.trim_end_matches(&['\r', '\n'][..]).to_string()
+}
+
 /// Print question to standard output and read answer from standard input.
 /// Give user multiple tries if she doesn't answer with a correct question.
 ///
 /// # Errors
 ///
 /// User didn't give a valid answer.
+#[allow(clippy::result_unit_err)]
 pub fn prompt<'a, T>(command: &'a Command<'a, T>) -> Result<&'a T, ()>
 where
     T: CmdOption,
@@ -15,16 +15,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::calendar::Calendar;
 use crate::card::Card;
-use crate::file::CardsReader;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::error::Error;
+use crate::note::link_siblings;
+use crate::progress::Progress;
 use crate::scheduler::Schedule;
-use fnv::FnvHashMap;
+use crate::storage::Storage;
+use chrono::NaiveDateTime;
+use fnv::{FnvHashMap, FnvHashSet};
 use std::collections::VecDeque;
 
 pub struct Qa {
     queued: VecDeque<Card>, // Cards yet to be scheduled
     scheduled: FnvHashMap<u64, Card>,
     schedule: Schedule,
+    progress: Progress,
+    new_per_day: usize,
+    // New cards scheduled for learning which haven't been assessed yet. They
+    // count towards the daily limit of new cards once assessed.
+    new: FnvHashSet<u64>,
+    // Cards postponed to another day because their sibling has been learned
+    // today.
+    buried: FnvHashSet<u64>,
+    bury_siblings: bool,
+    calendar: Calendar,
+    clock: Box<dyn Clock>,
+    storage: Box<dyn Storage>,
 }
 
 /// Question & Answer object consist of these parts:
@@ -35,21 +54,43 @@ pub struct Qa {
 ///     to the next card
 /// * FIFO queue of cards not yet schedule (i.e. learned). User can put more
 ///   cards to scheduler.
+/// * Today's progress used to enforce daily limits of new cards and reviews
+///   given by `Config`.
+/// * Set of buried cards. If enabled by `Config`, siblings of a card learned
+///   today are postponed to another day.
 impl Qa {
-    /// Initialize Question & Answer object from cards iterator. Schedule is
-    /// loaded from disk.
-    pub fn load(reader: CardsReader) -> Result<Qa, String> {
-        let schedule = Schedule::load()?;
+    /// Initialize Question & Answer object from cards, schedule and today's
+    /// progress loaded from `storage`. Due cards exceeding the daily limit of
+    /// reviews and siblings of cards learned earlier today are postponed. All
+    /// scheduling is done with current time given by `clock`.
+    pub fn load(
+        storage: Box<dyn Storage>,
+        config: &Config,
+        clock: Box<dyn Clock>,
+    ) -> Result<Qa, Error> {
+        let calendar = config.calendar();
+        let mut schedule = Schedule::load(storage.as_ref(), config, clock.as_ref())?;
+        let progress = Progress::load(storage.as_ref(), calendar.date(clock.now()))?;
+        schedule.limit_reviews(config.reviews_per_day().saturating_sub(progress.reviews()));
 
         let mut qa = Qa {
             queued: VecDeque::new(),
             scheduled: FnvHashMap::default(),
             schedule,
+            progress,
+            new_per_day: config.new_per_day(),
+            new: FnvHashSet::default(),
+            buried: FnvHashSet::default(),
+            bury_siblings: config.bury_siblings(),
+            calendar,
+            clock,
+            storage,
         };
 
-        for card_result in reader {
-            let card: Card = card_result?;
+        let mut cards = qa.storage.read_cards()?;
+        link_siblings(&mut cards);
 
+        for card in cards {
             if qa.schedule.has_item(card.id()) {
                 qa.scheduled.insert(card.id(), card);
             } else {
@@ -57,34 +98,110 @@ impl Qa {
             }
         }
 
+        let revisited: Vec<u64> = qa
+            .scheduled
+            .keys()
+            .cloned()
+            .filter(|&id| qa.schedule.is_revisited_today(id, qa.clock.as_ref()))
+            .collect();
+        for id in revisited {
+            qa.bury_siblings_of(id);
+        }
+
         Ok(qa)
     }
 
-    /// Save schedule to disk. Exiting schedule file is rewritten.
-    pub fn save(&self) -> Result<(), String> {
-        self.schedule.save()
+    /// Save schedule of cards learned since loading and today's progress to
+    /// the storage. New cards which haven't been assessed stay unscheduled.
+    pub fn save(&mut self) -> Result<(), Error> {
+        for id in self.new.drain() {
+            self.schedule.remove_item(id);
+        }
+        self.schedule.save(self.storage.as_mut())?;
+        self.progress.save(self.storage.as_mut())
+    }
+
+    /// Storage the cards, schedule and progress were loaded from.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
     }
 
-    /// Returns true if all cards scheduled for today has been learned.
-    pub fn is_today_schedule_done(&self) -> bool {
+    /// Returns true if all cards scheduled for today has been learned, except
+    /// cards in learning which are going to be due later today. Cards in
+    /// learning which already became due are scheduled along the way.
+    pub fn is_today_schedule_done(&mut self) -> bool {
+        self.schedule.promote_learning(self.clock.as_ref());
         self.schedule.is_done()
     }
 
-    /// Returns true if there is at least one card not yet scheduled.
+    /// Returns number of cards in learning which are going to be due later
+    /// today and (local) time when the first of them becomes due.
+    pub fn pending_learning(&self) -> Option<(usize, NaiveDateTime)> {
+        self.schedule
+            .next_learning()
+            .map(|next| (self.schedule.learning_count(), self.calendar.local(next)))
+    }
+
+    /// Returns true if there is no card not yet scheduled which could be
+    /// scheduled today (i.e. isn't buried).
     pub fn is_all_scheduled(&self) -> bool {
-        self.queued.is_empty()
+        self.queued
+            .iter()
+            .all(|card| self.buried.contains(&card.id()))
+    }
+
+    /// Postpones siblings of card with given ID to another day if sibling
+    /// burying is enabled.
+    fn bury_siblings_of(&mut self, id: u64) {
+        if !self.bury_siblings {
+            return;
+        }
+
+        let card = match self.scheduled.get(&id) {
+            Some(card) => card,
+            None => return,
+        };
+        for &sibling in card.siblings() {
+            self.schedule.bury(sibling);
+            self.buried.insert(sibling);
+        }
+    }
+
+    /// Schedule as many new cards as the daily limit of new cards allows.
+    pub fn schedule_new(&mut self) {
+        let count = self
+            .new_per_day
+            .saturating_sub(self.progress.new_cards() + self.new.len());
+        self.schedule_more(count);
     }
 
-    /// Schedule `count` new cards for learning.
+    /// Schedule `count` new cards for learning. The cards are counted towards
+    /// the daily limit of new cards once assessed but `count` is not limited
+    /// by it. Buried cards are skipped and stay queued.
     pub fn schedule_more(&mut self, count: usize) {
-        for _i in 0..count {
+        let mut skipped = Vec::new();
+
+        let mut scheduled = 0;
+        while scheduled < count {
             let card: Card = match self.queued.pop_front() {
                 Some(card) => card,
                 None => break,
             };
+            if self.buried.contains(&card.id()) {
+                skipped.push(card);
+                continue;
+            }
 
-            self.schedule.add_item(card.id());
-            self.scheduled.insert(card.id(), card);
+            let id = card.id();
+            self.schedule.add_item(id, self.clock.as_ref());
+            self.scheduled.insert(id, card);
+            self.new.insert(id);
+            self.bury_siblings_of(id);
+            scheduled += 1;
+        }
+
+        for card in skipped.into_iter().rev() {
+            self.queued.push_front(card);
         }
     }
 
@@ -97,6 +214,137 @@ impl Qa {
     /// Assess "easiness" of current card and move current the next one.
     /// Easiness spans from 0 to 5.
     pub fn assess_current(&mut self, q: u8) {
-        self.schedule.update_current(q);
+        if self.schedule.is_current_review() {
+            self.progress.add_review();
+        }
+        let id = self.schedule.current();
+        if self.new.remove(&id) {
+            self.progress.add_new_card();
+        }
+        self.bury_siblings_of(id);
+        self.schedule.update_current(q, self.clock.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::scheduler::ScheduleItem;
+    use crate::storage::MemoryStorage;
+    use chrono::{TimeZone, Utc};
+
+    /// Returns a copy of cards, schedule and progress saved by `qa`.
+    fn reload(qa: &Qa) -> Box<dyn Storage> {
+        let calendar = Config::default().calendar();
+        let mut storage = MemoryStorage::new();
+        storage
+            .store_cards(&qa.storage().read_cards().unwrap())
+            .unwrap();
+        let schedule = qa.storage().read_schedule(&calendar).unwrap();
+        let items: Vec<(u64, &ScheduleItem)> =
+            schedule.iter().map(|(id, item)| (*id, item)).collect();
+        storage.write_schedule(&items).unwrap();
+        if let Some(progress) = qa.storage().read_progress().unwrap() {
+            storage.write_progress(&progress).unwrap();
+        }
+        Box::new(storage)
+    }
+
+    #[test]
+    fn test_new_per_day() {
+        let config = Config::parse("timezone = UTC\nnew_per_day = 3\nfuzz = 0").unwrap();
+        let clock = || Box::new(FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(12, 0, 0)));
+        let mut storage = MemoryStorage::new();
+        let cards: Vec<Card> = (1..6)
+            .map(|id| Card::new(id, format!("q{}", id), format!("a{}", id)))
+            .collect();
+        storage.store_cards(&cards).unwrap();
+
+        // The session is left after the first card, the other two are not
+        // counted and stay unscheduled.
+        let mut qa = Qa::load(Box::new(storage), &config, clock()).unwrap();
+        qa.schedule_new();
+        assert_eq!(qa.new.len(), 3);
+        assert_eq!(qa.current_card().id(), 1);
+        qa.assess_current(5);
+        qa.save().unwrap();
+        assert_eq!(
+            qa.storage().read_progress().unwrap().unwrap().new_cards(),
+            1
+        );
+        let calendar = config.calendar();
+        assert_eq!(qa.storage().read_schedule(&calendar).unwrap().len(), 1);
+
+        // Another session the same day introduces the rest of the limit.
+        let mut qa = Qa::load(reload(&qa), &config, clock()).unwrap();
+        qa.schedule_new();
+        let mut new: Vec<u64> = qa.new.iter().cloned().collect();
+        new.sort_unstable();
+        assert_eq!(new, vec![2, 3]);
+
+        // More cards can still be added explicitly.
+        qa.schedule_more(8);
+        assert_eq!(qa.new.len(), 4);
+        assert!(qa.is_all_scheduled());
+    }
+
+    #[test]
+    fn test_reviews_per_day() {
+        let config = Config::parse("timezone = UTC\nreviews_per_day = 1\nfuzz = 0").unwrap();
+        let calendar = config.calendar();
+        let mut storage = MemoryStorage::new();
+        let cards: Vec<Card> = (1..4)
+            .map(|id| Card::new(id, format!("q{}", id), format!("a{}", id)))
+            .collect();
+        storage.store_cards(&cards).unwrap();
+        let schedule: Vec<(u64, ScheduleItem)> = (1..4)
+            .map(|id| {
+                let line = format!(
+                    "{}\t2019-03-10T04:00:00Z\t2019-03-04T08:00:00Z\t2\t2.5\treview",
+                    Card::serialize_id(id)
+                );
+                ScheduleItem::deserialize(&line, &calendar).unwrap()
+            })
+            .collect();
+        let items: Vec<(u64, &ScheduleItem)> =
+            schedule.iter().map(|(id, item)| (*id, item)).collect();
+        storage.write_schedule(&items).unwrap();
+
+        let clock = || Box::new(FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(12, 0, 0)));
+        let mut qa = Qa::load(Box::new(storage), &config, clock()).unwrap();
+        assert!(!qa.is_today_schedule_done());
+        qa.assess_current(5);
+        assert!(qa.is_today_schedule_done());
+        qa.save().unwrap();
+        assert_eq!(qa.storage().read_progress().unwrap().unwrap().reviews(), 1);
+
+        // The limit holds across sessions.
+        let mut qa = Qa::load(reload(&qa), &config, clock()).unwrap();
+        assert!(qa.is_today_schedule_done());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+        let cards = [
+            Card::new(1, "Hund".to_string(), "dog".to_string()),
+            Card::new(2, "Katze".to_string(), "cat".to_string()),
+        ];
+        storage.store_cards(&cards).unwrap();
+
+        let clock = FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(12, 0, 0));
+        let mut qa = Qa::load(Box::new(storage), &Config::default(), Box::new(clock)).unwrap();
+        qa.schedule_more(1);
+        assert_eq!(qa.current_card().id(), 1);
+        qa.assess_current(5);
+        qa.save().unwrap();
+
+        let calendar = Config::default().calendar();
+        let schedule = qa.storage().read_schedule(&calendar).unwrap();
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule[0].0, 1);
+        let progress = qa.storage().read_progress().unwrap().unwrap();
+        assert_eq!(progress.new_cards(), 1);
     }
 }
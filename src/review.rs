@@ -0,0 +1,105 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::card::Card;
+use crate::error::Error;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// A single assessment of a card during learning. The log of all reviews is
+/// kept so that schedule of a card can be recomputed, e.g. when it was
+/// learned on two devices.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Review {
+    card: u64,
+    time: DateTime<Utc>,
+    quality: u8,
+}
+
+impl Review {
+    /// Review of card with ID `card` assessed with easiness `quality` (0 - 5)
+    /// at `time`.
+    pub fn new(card: u64, time: DateTime<Utc>, quality: u8) -> Review {
+        Review {
+            card,
+            time,
+            quality,
+        }
+    }
+
+    /// ID of the reviewed card.
+    pub fn card(&self) -> u64 {
+        self.card
+    }
+
+    /// Time of the assessment.
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// User assessed easiness of the card, 0 - 5.
+    pub fn quality(&self) -> u8 {
+        self.quality
+    }
+
+    /// Serialize the review to a line (ending with line-feed) of TAB
+    /// separated card ID, time and easiness.
+    pub fn serialize(&self) -> String {
+        format!(
+            "{}\t{}\t{}\n",
+            Card::serialize_id(self.card),
+            self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.quality
+        )
+    }
+
+    pub fn deserialize(line: &str) -> Result<Review, Error> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 3 {
+            let reason = format!("Expected three TAB separated tokens, got: {}", line);
+            return Err(Error::parse(reason));
+        }
+
+        let card = Card::parse_id(parts[0])?;
+        let time = DateTime::parse_from_rfc3339(parts[1])
+            .map_err(|reason| Error::field_source("review time", reason))?
+            .with_timezone(&Utc);
+        let quality: u8 = match parts[2].parse() {
+            Ok(quality) if quality <= 5 => quality,
+            _ => return Err(Error::field("easiness", parts[2])),
+        };
+
+        Ok(Review::new(card, time, quality))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_serialization() {
+        let review = Review::new(10, Utc.ymd(2019, 3, 10).and_hms_milli(8, 0, 0, 250), 4);
+        let line = "000000000000000a\t2019-03-10T08:00:00.250Z\t4\n";
+        assert_eq!(review.serialize(), line);
+        assert_eq!(Review::deserialize(line.trim_end()).unwrap(), review);
+
+        assert!(Review::deserialize("000000000000000a\t2019-03-10T08:00:00Z\t6").is_err());
+        assert!(Review::deserialize("000000000000000a\t2019-03-10\t4").is_err());
+        assert!(Review::deserialize("000000000000000a\t4").is_err());
+    }
+}
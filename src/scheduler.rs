@@ -25,21 +25,73 @@
 //    http://www.supermemo.com
 //    http://www.supermemo.eu
 
+use crate::calendar::Calendar;
 use crate::card::Card;
-use crate::file::get_vole_dir;
-use chrono::{prelude::*, Duration, NaiveDate};
-use fnv::FnvHashMap;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::error::Error;
+use crate::review::Review;
+use crate::storage::Storage;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, SecondsFormat, Utc};
+use fnv::{FnvHashMap, FnvHashSet};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::collections::VecDeque;
-use std::fs::{rename, File};
-use std::io::{BufRead, BufReader, ErrorKind, Write};
 
-const SCHEDULE_FILE_NAME: &str = "schedule.txt";
+/// Lowest easiness factor of SM-2, harder items are still repeated in
+/// increasing intervals.
+pub const MIN_EF: f32 = 1.3;
 
-struct ScheduleItem {
+/// Phase of learning of a `ScheduleItem`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    /// A new item repeated in short (sub-day) steps before it graduates to
+    /// review. The number is index of the current learning step.
+    Learning(usize),
+    /// An item repeated in intervals of days computed by SM-2.
+    Review,
+    /// A forgotten item repeated in short steps before it returns to review.
+    /// The number is index of the current relearning step.
+    Relearning(usize),
+}
+
+impl Phase {
+    fn serialize(self) -> String {
+        match self {
+            Phase::Learning(step) => format!("learning:{}", step),
+            Phase::Review => "review".to_string(),
+            Phase::Relearning(step) => format!("relearning:{}", step),
+        }
+    }
+
+    fn deserialize(source: &str) -> Result<Phase, Error> {
+        let invalid = || Error::field("phase", source);
+
+        if source == "review" {
+            return Ok(Phase::Review);
+        }
+
+        let mut parts = source.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let step: usize = match parts.next() {
+            Some(step) => step.parse().map_err(|_| invalid())?,
+            None => return Err(invalid()),
+        };
+        match name {
+            "learning" => Ok(Phase::Learning(step)),
+            "relearning" => Ok(Phase::Relearning(step)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Scheduling state of a single card.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleItem {
     iteration: u32,
     ef: f32,
-    last_revisit: NaiveDate,
-    next_revisit: NaiveDate,
+    phase: Phase,
+    last_revisit: DateTime<Utc>,
+    next_revisit: DateTime<Utc>,
 }
 
 pub struct Schedule {
@@ -47,76 +99,151 @@ pub struct Schedule {
     stage: usize,
     hot_stage: VecDeque<u64>,
     refresh_stage: VecDeque<u64>,
+    // Items in (re)learning which are going to be due later today, ordered
+    // by time of their next revisit.
+    learning_stage: VecDeque<u64>,
+    // Items due for a review which haven't been assessed yet.
+    pending_reviews: FnvHashSet<u64>,
+    // Items added or assessed since the schedule was loaded.
+    changed: FnvHashSet<u64>,
+    // Reviews made since the schedule was loaded.
+    reviews: Vec<Review>,
+    config: Config,
 }
 
-fn today() -> NaiveDate {
-    Local::today().naive_local()
-}
-
-impl Default for ScheduleItem {
-    fn default() -> ScheduleItem {
+impl ScheduleItem {
+    /// Creates a freshly initialized item to be learned from `now`.
+    fn new(now: DateTime<Utc>) -> ScheduleItem {
         ScheduleItem {
             iteration: 0,
             ef: 2.5,
+            phase: Phase::Learning(0),
             // note that time between last_revisit and today does play any
-            // role only after first two visits so it is possible set today
+            // role only after first two visits so it is possible set now
             // without any harm.
-            last_revisit: today(),
-            next_revisit: today(),
+            last_revisit: now,
+            next_revisit: now,
         }
     }
-}
 
-impl ScheduleItem {
-    fn serialize(&self, id: u64) -> String {
-        let last_revisit = self.last_revisit.format("%Y-%m-%d");
-        let next_revisit = self.next_revisit.format("%Y-%m-%d");
+    /// Creates an item from its stored parts, see `ScheduleItem::serialize`
+    /// for format of `phase`.
+    pub fn from_parts(
+        next_revisit: DateTime<Utc>,
+        last_revisit: DateTime<Utc>,
+        iteration: u32,
+        ef: f32,
+        phase: &str,
+    ) -> Result<ScheduleItem, Error> {
+        Ok(ScheduleItem {
+            iteration,
+            ef,
+            phase: Phase::deserialize(phase)?,
+            last_revisit,
+            next_revisit,
+        })
+    }
+
+    /// Time of the next revisit of the card.
+    pub fn next_revisit(&self) -> DateTime<Utc> {
+        self.next_revisit
+    }
+
+    /// Time of the last revisit of the card.
+    pub fn last_revisit(&self) -> DateTime<Utc> {
+        self.last_revisit
+    }
+
+    /// Number of successful repetitions of the card in a row.
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    /// Easiness factor of the item.
+    pub fn ef(&self) -> f32 {
+        self.ef
+    }
+
+    /// Phase of learning, e.g. `review` or `learning:1`.
+    pub fn phase(&self) -> String {
+        self.phase.serialize()
+    }
+
+    /// Resets easiness factor lower than `MIN_EF` to `MIN_EF` and infinite
+    /// or not a number easiness factor to the initial value.
+    pub fn clamp_ef(&mut self) {
+        if !self.ef.is_finite() {
+            self.ef = 2.5;
+        } else if self.ef < MIN_EF {
+            self.ef = MIN_EF;
+        }
+    }
+
+    /// Moves the last revisit (and next revisit if it isn't later) from the
+    /// future to `now`.
+    pub fn clamp_last_revisit(&mut self, now: DateTime<Utc>) {
+        if self.last_revisit > now {
+            self.last_revisit = now;
+            self.next_revisit = self.next_revisit.max(now);
+        }
+    }
+
+    /// Serialize the item of card with ID `id` to a line (ending with
+    /// line-feed) of TAB separated ID, next and last revisit, iteration, EF
+    /// and phase.
+    pub fn serialize(&self, id: u64) -> String {
+        let last_revisit = self.last_revisit.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let next_revisit = self.next_revisit.to_rfc3339_opts(SecondsFormat::Secs, true);
         format!(
-            "{id}\t{next_revisit}\t{last_revisit}\t{iteration}\t{ef}\n",
+            "{id}\t{next_revisit}\t{last_revisit}\t{iteration}\t{ef}\t{phase}\n",
             id = Card::serialize_id(id),
             next_revisit = next_revisit,
             last_revisit = last_revisit,
             iteration = self.iteration,
-            ef = self.ef
+            ef = self.ef,
+            phase = self.phase.serialize()
         )
     }
 
     /// Parse `ScheduleItem` and its ID (hence the tuple) from a text line.
-    fn deserialize(line: &str) -> Result<(u64, ScheduleItem), String> {
-        fn parse_date(source: &str) -> Result<NaiveDate, String> {
-            match NaiveDate::parse_from_str(source, "%Y-%m-%d") {
-                Ok(date) => Ok(date),
-                Err(reason) => Err(format!("Failed to parse date: {}", reason)),
+    /// Lines written by older versions of VoLe contain only dates and no
+    /// phase; these dates are interpreted as starts of learning days of
+    /// `calendar`.
+    pub fn deserialize(line: &str, calendar: &Calendar) -> Result<(u64, ScheduleItem), Error> {
+        let parse_time = |source: &str| -> Result<DateTime<Utc>, Error> {
+            if let Ok(date) = NaiveDate::parse_from_str(source, "%Y-%m-%d") {
+                return Ok(calendar.start(date));
             }
-        }
+            match DateTime::parse_from_rfc3339(source) {
+                Ok(time) => Ok(time.with_timezone(&Utc)),
+                Err(reason) => Err(Error::field_source("date", reason)),
+            }
+        };
 
         let parts: Vec<&str> = line.split('\t').collect();
-        if parts.len() != 5 {
-            let reason = format!("Expected five TAB separated tokens, got: {}", line);
-            return Err(reason);
+        if parts.len() != 5 && parts.len() != 6 {
+            let reason = format!("Expected five or six TAB separated tokens, got: {}", line);
+            return Err(Error::parse(reason));
         }
 
         let id: u64 = Card::parse_id(parts[0])?;
-        let next_revisit: NaiveDate = parse_date(parts[1])?;
-        let last_revisit: NaiveDate = parse_date(parts[2])?;
-        let iteration: u32 = match parts[3].parse() {
-            Ok(iteration) => iteration,
-            Err(reason) => {
-                let msg = format!("Failed to parse iteration: {}", reason);
-                return Err(msg);
-            }
-        };
-        let ef: f32 = match parts[4].parse() {
-            Ok(ef) => ef,
-            Err(reason) => {
-                let msg = format!("Failed to parse EF: {}", reason);
-                return Err(msg);
-            }
+        let next_revisit = parse_time(parts[1])?;
+        let last_revisit = parse_time(parts[2])?;
+        let iteration: u32 = parts[3]
+            .parse()
+            .map_err(|reason| Error::field_source("iteration", reason))?;
+        let ef: f32 = parts[4]
+            .parse()
+            .map_err(|reason| Error::field_source("EF", reason))?;
+        let phase = match parts.get(5) {
+            Some(phase) => Phase::deserialize(phase)?,
+            None => Phase::Review,
         };
 
         let item = ScheduleItem {
             iteration,
             ef,
+            phase,
             last_revisit,
             next_revisit,
         };
@@ -124,24 +251,69 @@ impl ScheduleItem {
         Ok((id, item))
     }
 
-    /// Returns number of days since last revisit.
-    fn days_since(&self) -> u32 {
-        let duration = self.last_revisit - today();
-        let days = duration.num_days();
-        if days < 0 {
-            panic!("Item was visited in the future.");
+    /// Replays reviews of card with ID `id` made after the last revisit of
+    /// `base` in order of their time, e.g. to merge reviews of the card made
+    /// on more devices. A fresh item is used if there is no `base`. Intervals
+    /// are scheduled by `config` and fuzzed without load balancing. Returns
+    /// `None` if there are no such reviews.
+    pub fn replay(
+        base: Option<&ScheduleItem>,
+        id: u64,
+        reviews: &[Review],
+        config: &Config,
+    ) -> Option<ScheduleItem> {
+        let mut reviews: Vec<&Review> = reviews
+            .iter()
+            .filter(|review| review.card() == id)
+            .filter(|review| base.is_none_or(|base| review.time() > base.last_revisit))
+            .collect();
+        reviews.sort_by_key(|review| review.time());
+        let first = reviews.first()?;
+
+        let calendar = config.calendar();
+        let mut item = match base {
+            Some(base) => base.clone(),
+            None => ScheduleItem::new(first.time()),
+        };
+        for review in &reviews {
+            let today = calendar.date(review.time());
+            if let Some(interval) = item.update(review.quality(), review.time(), config) {
+                let interval = match fuzz_range(config.fuzz(), interval) {
+                    Some((min, max)) => fuzz_rng(id, item.iteration, today).gen_range(min, max + 1),
+                    None => interval,
+                };
+                item.reschedule(today, interval, &calendar);
+            }
         }
-        days as u32
+        Some(item)
+    }
+
+    /// Returns number of learning days since last revisit till `today`.
+    /// Items revisited in the future, e.g. on a device with a wrong clock,
+    /// were revisited zero days ago.
+    fn days_since(&self, today: NaiveDate, calendar: &Calendar) -> u32 {
+        let duration = today - calendar.date(self.last_revisit);
+        duration.num_days().max(0) as u32
     }
 
-    /// Marks `self` as visited today.
-    fn revisit(&mut self) {
-        self.last_revisit = today();
+    /// Returns number of learning days between last revisit and the day the
+    /// item was scheduled to.
+    fn scheduled_interval(&self, calendar: &Calendar) -> u32 {
+        let days = (calendar.date(self.next_revisit) - calendar.date(self.last_revisit)).num_days();
+        days.max(0) as u32
     }
 
-    /// Schedule `self` n days to the future.
-    fn reschedule(&mut self, days: u32) {
-        self.next_revisit = today() + Duration::days(i64::from(days));
+    /// Returns how many times longer than its scheduled interval is the item
+    /// overdue on day `today`. Items due today or later have zero
+    /// overdueness.
+    fn overdueness(&self, today: NaiveDate, calendar: &Calendar) -> f32 {
+        let overdue = (today - calendar.date(self.next_revisit)).num_days().max(0);
+        overdue as f32 / self.scheduled_interval(calendar).max(1) as f32
+    }
+
+    /// Schedule `self` to the start of n-th learning day after `today`.
+    fn reschedule(&mut self, today: NaiveDate, days: u32, calendar: &Calendar) {
+        self.next_revisit = calendar.start(today + Duration::days(i64::from(days)));
     }
 
     /// Recompute easiness factor based on user assessed easiness (0 - 5).
@@ -152,150 +324,257 @@ impl ScheduleItem {
 
         let q = f32::from(q);
         self.ef = self.ef - 0.8 + 0.28 * q - 0.02 * q * q;
-        if self.ef < 1.3 {
-            self.ef = 1.3;
+        if self.ef < MIN_EF {
+            self.ef = MIN_EF;
         }
     }
 
+    /// Returns true if the item is in learning or relearning phase.
+    fn is_learning(&self) -> bool {
+        self.phase != Phase::Review
+    }
+
     /// Reschedule, recompute easiness and reset iteration based on user
-    /// provided easiness assessment.
-    fn update(&mut self, q: u8) {
+    /// provided easiness assessment made at `now`.
+    ///
+    /// Items in (re)learning advance through learning steps of `config` on
+    /// success and return to the first step on failure. Items which pass the
+    /// last step graduate and are scheduled on daily basis by SM-2. Forgotten
+    /// items in review are moved to relearning.
+    ///
+    /// Items scheduled on daily basis are not rescheduled by this method,
+    /// instead number of days computed by SM-2 is returned and the item has
+    /// to be rescheduled with `self.reschedule()`.
+    fn update(&mut self, q: u8, now: DateTime<Utc>, config: &Config) -> Option<u32> {
         self.update_ef(q);
 
         if q < 3 {
             self.iteration = 0;
+        }
+
+        let (step, relearning) = match self.phase {
+            Phase::Learning(step) => (Some(step + 1), false),
+            Phase::Relearning(step) => (Some(step + 1), true),
+            Phase::Review => (None, true),
+        };
+        let step = if q < 3 { Some(0) } else { step };
+        let steps = if relearning {
+            config.relearning_steps()
         } else {
-            self.iteration += 1;
-            if self.iteration < 1 {
-                panic!("Iteration cannot be smaller than 1 at this stage.");
+            config.learning_steps()
+        };
+
+        match step {
+            Some(step) if step < steps.len() || q < 3 => {
+                self.phase = if relearning {
+                    Phase::Relearning(step)
+                } else {
+                    Phase::Learning(step)
+                };
+                // Items failed without any (re)learning steps are repeated
+                // immediately.
+                self.next_revisit = now + steps.get(step).cloned().unwrap_or_else(Duration::zero);
+                self.last_revisit = now;
+                None
             }
-            if self.iteration == 1 {
-                self.reschedule(1);
-            } else if self.iteration == 2 {
-                self.reschedule(6);
-            } else {
-                let interval: u32 = (self.ef * self.days_since() as f32) as u32;
-                self.reschedule(interval);
+            _ => {
+                self.phase = Phase::Review;
+                let interval = self.graduate(q, config.calendar().date(now), &config.calendar());
+                self.last_revisit = now;
+                Some(interval)
             }
         }
+    }
 
-        self.revisit();
+    /// Successful review according to SM-2 with easiness `q` on day `today`.
+    /// Returns number of days till the next revisit.
+    ///
+    /// Items recalled after their scheduled day are credited with a part of
+    /// the delay: the easier the recall, the bigger part of the delay is
+    /// added to the scheduled interval the next interval is based on.
+    fn graduate(&mut self, q: u8, today: NaiveDate, calendar: &Calendar) -> u32 {
+        self.iteration += 1;
+        if self.iteration < 1 {
+            panic!("Iteration cannot be smaller than 1 at this stage.");
+        }
+        if self.iteration == 1 {
+            return 1;
+        }
+
+        let scheduled = self.scheduled_interval(calendar);
+        let overdue = self.days_since(today, calendar).saturating_sub(scheduled);
+        let credit = match q {
+            5 => overdue,
+            4 => overdue / 2,
+            _ => overdue / 4,
+        };
+        let interval = (self.ef * (scheduled + credit) as f32) as u32;
+
+        if self.iteration == 2 {
+            interval.max(6)
+        } else {
+            interval
+        }
     }
 }
 
-impl Default for Schedule {
-    fn default() -> Schedule {
+/// Returns range of days `interval` is randomly spread within by `fuzz`,
+/// `None` if the interval is not fuzzed.
+fn fuzz_range(fuzz: f32, interval: u32) -> Option<(u32, u32)> {
+    if fuzz <= 0.0 || interval < 3 {
+        return None;
+    }
+    let delta = ((interval as f32 * fuzz).round() as u32).max(1);
+    Some((interval.saturating_sub(delta).max(1), interval + delta))
+}
+
+/// Random number generator for fuzzing of item with given ID and iteration
+/// reviewed on day `today`.
+fn fuzz_rng(id: u64, iteration: u32, today: NaiveDate) -> StdRng {
+    let iteration = u64::from(iteration);
+    let day = today.num_days_from_ce() as u64;
+    StdRng::seed_from_u64(id ^ iteration.rotate_left(32) ^ day.rotate_left(48))
+}
+
+impl Schedule {
+    /// Creates an empty schedule. Items are scheduled according to learning
+    /// days and learning steps of `config`.
+    pub fn new(config: &Config) -> Schedule {
         Schedule {
             items: FnvHashMap::default(),
             stage: 0,
             hot_stage: VecDeque::new(),
             refresh_stage: VecDeque::new(),
+            learning_stage: VecDeque::new(),
+            pending_reviews: FnvHashSet::default(),
+            changed: FnvHashSet::default(),
+            reviews: Vec::new(),
+            config: config.clone(),
         }
     }
-}
 
-impl Schedule {
-    pub fn load() -> Result<Schedule, String> {
-        let mut path = get_vole_dir()?;
-        path.push(&SCHEDULE_FILE_NAME);
+    /// Load schedule from `storage`. Items are scheduled according to
+    /// learning days and learning steps of `config`, items due at the
+    /// current time of `clock` are scheduled for learning.
+    pub fn load(
+        storage: &dyn Storage,
+        config: &Config,
+        clock: &dyn Clock,
+    ) -> Result<Schedule, Error> {
+        let mut schedule = Schedule::new(config);
+        let calendar = config.calendar();
+        let now = clock.now();
+        let today = calendar.date(now);
 
-        let mut schedule: Schedule = Default::default();
+        for (id, item) in storage.read_schedule(&calendar)? {
+            let due = item.next_revisit <= now;
+            let learning = item.is_learning();
+            let due_today = calendar.date(item.next_revisit) == today;
+            schedule.items.insert(id, item);
 
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(error) => {
-                if let ErrorKind::NotFound = error.kind() {
-                    return Ok(schedule);
+            if due {
+                schedule.hot_stage.push_back(id);
+                if !learning {
+                    schedule.pending_reviews.insert(id);
                 }
-                let reason = format!(
-                    "Couldn't open file \"{}\": {}",
-                    path.to_string_lossy(),
-                    error
-                );
-                return Err(reason);
+            } else if learning && due_today {
+                schedule.enqueue_learning(id);
             }
-        };
+        }
 
-        let reader = BufReader::new(&file);
-        let numbered_lines = reader
-            .lines()
-            .enumerate()
-            .map(|(i, result)| (i + 1, result));
-
-        for (line_nr, result) in numbered_lines {
-            let line = match result {
-                Ok(line) => line,
-                Err(error) => {
-                    let reason = format!(
-                        "Couldn't read file \"{}\": {}",
-                        path.to_string_lossy(),
-                        error
-                    );
-                    return Err(reason);
-                }
-            };
+        schedule.arrange_reviews(today);
+        Ok(schedule)
+    }
+
+    /// Orders due reviews by their relative overdueness on day `today`, the
+    /// most overdue first. If the number of reviews overdue from previous
+    /// days exceeds backlog limit, the least overdue of them are postponed so
+    /// that the backlog is spread over several days. Due items in learning
+    /// are kept at the front.
+    fn arrange_reviews(&mut self, today: NaiveDate) {
+        let calendar = self.config.calendar();
+        let items = &self.items;
+        let pending_reviews = &self.pending_reviews;
+
+        let (learning, mut reviews): (Vec<u64>, Vec<u64>) = self
+            .hot_stage
+            .iter()
+            .partition(|id| !pending_reviews.contains(id));
+        reviews.sort_by(|a, b| {
+            let a_overdueness = items[a].overdueness(today, &calendar);
+            let b_overdueness = items[b].overdueness(today, &calendar);
+            b_overdueness
+                .partial_cmp(&a_overdueness)
+                .unwrap()
+                .then(a.cmp(b))
+        });
 
-            let (id, item) = match ScheduleItem::deserialize(&line) {
-                Ok(parsed) => parsed,
-                Err(reason) => {
-                    let msg = format!("Error on line {}: {}", line_nr, reason);
-                    return Err(msg);
+        if let Some(limit) = self.config.backlog_per_day() {
+            let mut backlog = 0;
+            reviews.retain(|id| {
+                if calendar.date(items[id].next_revisit) >= today {
+                    return true;
                 }
-            };
-            if item.next_revisit <= today() {
-                schedule.hot_stage.push_back(id);
-            }
-            schedule.items.insert(id, item);
+                backlog += 1;
+                backlog <= limit
+            });
         }
 
-        Ok(schedule)
+        self.pending_reviews = reviews.iter().cloned().collect();
+        self.hot_stage = learning.into_iter().chain(reviews).collect();
     }
 
-    /// Saves schedule to disc and overwrites schedule file if it already
-    /// exists.
-    pub fn save(&self) -> Result<(), String> {
-        let mut path = get_vole_dir()?;
-        let mut tmp_path = path.clone();
-        path.push(&SCHEDULE_FILE_NAME);
-        tmp_path.push(format!("{}.tmp", &SCHEDULE_FILE_NAME));
-
-        {
-            let mut file = match File::create(&tmp_path) {
-                Ok(file) => file,
-                Err(error) => {
-                    let reason = format!(
-                        "Couldn't open file \"{}\": {}",
-                        tmp_path.to_string_lossy(),
-                        error
-                    );
-                    return Err(reason);
-                }
-            };
-
-            for (id, item) in &self.items {
-                let line = item.serialize(*id);
-                if let Err(error) = file.write_all(line.as_bytes()) {
-                    let reason = format!(
-                        "Couldn't append to file \"{}\": {}",
-                        tmp_path.to_string_lossy(),
-                        error
-                    );
-                    return Err(reason);
-                }
-            }
+    /// Saves items added or assessed and reviews made since the schedule was
+    /// loaded (or last saved) to `storage`. Other items are kept as they are
+    /// stored, thus changes made by other processes in the meantime (e.g.
+    /// sync) are not overwritten.
+    pub fn save(&mut self, storage: &mut dyn Storage) -> Result<(), Error> {
+        let items: Vec<(u64, &ScheduleItem)> = self
+            .changed
+            .iter()
+            .map(|&id| (id, &self.items[&id]))
+            .collect();
+        storage.update_schedule(&items, &self.config.calendar())?;
+        storage.store_reviews(&self.reviews)?;
+        self.changed.clear();
+        self.reviews.clear();
+        Ok(())
+    }
+
+    /// Randomly spreads `interval` (in days) of item with given ID reviewed on
+    /// day `today` within fuzz range given by configuration. If load
+    /// balancing is enabled, the least busy day in the range is chosen.
+    ///
+    /// Random number generator is seeded with item ID, its iteration and
+    /// `today` so fuzzing is reproducible.
+    fn fuzz(&self, id: u64, today: NaiveDate, interval: u32) -> u32 {
+        let (min, max) = match fuzz_range(self.config.fuzz(), interval) {
+            Some(range) => range,
+            None => return interval,
+        };
+        let mut rng = fuzz_rng(id, self.items[&id].iteration, today);
+
+        if !self.config.load_balance() {
+            return rng.gen_range(min, max + 1);
         }
 
-        if let Err(error) = rename(&tmp_path, &path) {
-            let reason = format!(
-                "Couldn't rename \"{}\" to \"{}\": {}",
-                tmp_path.to_string_lossy(),
-                path.to_string_lossy(),
-                error
-            );
-            return Err(reason);
+        let calendar = self.config.calendar();
+        let mut load: FnvHashMap<u32, usize> = FnvHashMap::default();
+        for item in self.items.values() {
+            let days = (calendar.date(item.next_revisit) - today).num_days();
+            if item.phase == Phase::Review && days >= i64::from(min) && days <= i64::from(max) {
+                *load.entry(days as u32).or_insert(0) += 1;
+            }
         }
 
-        Ok(())
+        let least = (min..=max)
+            .map(|days| load.get(&days).cloned().unwrap_or(0))
+            .min()
+            .unwrap();
+        let candidates: Vec<u32> = (min..=max)
+            .filter(|days| load.get(days).cloned().unwrap_or(0) == least)
+            .collect();
+        candidates[rng.gen_range(0, candidates.len())]
     }
 
     /// Returns true if item with given ID is already tracked in the schedule.
@@ -303,23 +582,133 @@ impl Schedule {
         self.items.contains_key(&id)
     }
 
-    /// Creates a new freshly initialized item to be learned.
+    /// Creates a new freshly initialized item to be learned from the current
+    /// time of `clock`.
     ///
     /// # Panics
     ///
     /// This method panics if the added item has been already added in the
     /// past.
-    pub fn add_item(&mut self, id: u64) {
+    pub fn add_item(&mut self, id: u64, clock: &dyn Clock) {
         if self.has_item(id) {
             panic!("Item with ID {} is already scheduled.", id);
         }
 
         self.hot_stage.push_back(id);
-        let item: ScheduleItem = Default::default();
+        let item = ScheduleItem::new(clock.now());
         self.items.insert(id, item);
+        self.changed.insert(id);
+    }
+
+    /// Removes item with given ID from the schedule as if it has never been
+    /// added, e.g. a new item which hasn't been assessed. It is not saved.
+    pub fn remove_item(&mut self, id: u64) {
+        self.bury(id);
+        self.items.remove(&id);
+        self.changed.remove(&id);
+    }
+
+    /// Insert item with given ID to the queue of items in learning keeping
+    /// the queue ordered by time of next revisit.
+    fn enqueue_learning(&mut self, id: u64) {
+        let items = &self.items;
+        let next_revisit = items[&id].next_revisit;
+        let position = self
+            .learning_stage
+            .iter()
+            .position(|other| items[other].next_revisit > next_revisit)
+            .unwrap_or(self.learning_stage.len());
+        self.learning_stage.insert(position, id);
     }
 
-    /// Returns true if all items have been learned for today. New items to be
+    /// Moves items in learning which became due at the current time of
+    /// `clock` to the front of the queue of items to be assessed. If there is
+    /// nothing else to learn, items due within learn ahead limit are moved
+    /// too.
+    pub fn promote_learning(&mut self, clock: &dyn Clock) {
+        let mut limit = clock.now();
+        if self.is_done() {
+            limit = limit + self.config.learn_ahead();
+        }
+
+        let mut due = Vec::new();
+        while let Some(&id) = self.learning_stage.front() {
+            if self.items[&id].next_revisit > limit {
+                break;
+            }
+            due.push(id);
+            self.learning_stage.pop_front();
+        }
+
+        if due.is_empty() {
+            return;
+        }
+        for id in due.into_iter().rev() {
+            self.hot_stage.push_front(id);
+        }
+        self.stage = 0;
+    }
+
+    /// Returns time of the next revisit of the first item in learning which
+    /// is going to be due later today.
+    pub fn next_learning(&self) -> Option<DateTime<Utc>> {
+        self.learning_stage
+            .front()
+            .map(|id| self.items[id].next_revisit)
+    }
+
+    /// Returns number of items in learning which are going to be due later
+    /// today.
+    pub fn learning_count(&self) -> usize {
+        self.learning_stage.len()
+    }
+
+    /// Postpones all but first `count` due items which haven't been reviewed
+    /// yet. Postponed items stay due and are going to be reviewed another
+    /// day.
+    pub fn limit_reviews(&mut self, count: usize) {
+        let mut kept = 0;
+        let pending_reviews = &mut self.pending_reviews;
+        self.hot_stage.retain(|id| {
+            if !pending_reviews.contains(id) {
+                return true;
+            }
+            if kept < count {
+                kept += 1;
+                return true;
+            }
+            pending_reviews.remove(id);
+            false
+        });
+    }
+
+    /// Returns true if item with given ID has been revisited during the
+    /// current learning day of `clock`.
+    pub fn is_revisited_today(&self, id: u64, clock: &dyn Clock) -> bool {
+        let calendar = self.config.calendar();
+        match self.items.get(&id) {
+            Some(item) => calendar.date(item.last_revisit) == calendar.date(clock.now()),
+            None => false,
+        }
+    }
+
+    /// Removes item with given ID from all queues of items to be learned
+    /// today. The item itself is kept intact so it remains due and is going
+    /// to be learned another day.
+    pub fn bury(&mut self, id: u64) {
+        self.hot_stage.retain(|&other| other != id);
+        self.refresh_stage.retain(|&other| other != id);
+        self.learning_stage.retain(|&other| other != id);
+        self.pending_reviews.remove(&id);
+        if self.hot_stage.is_empty() && !self.refresh_stage.is_empty() {
+            self.stage = 1;
+        } else {
+            self.stage = 0;
+        }
+    }
+
+    /// Returns true if all items due now have been learned. Items in learning
+    /// become due later with `self.promote_learning()` and new items to be
     /// learned can be added with `self.add_item()`.
     pub fn is_done(&self) -> bool {
         self.hot_stage.is_empty() && self.refresh_stage.is_empty()
@@ -341,9 +730,16 @@ impl Schedule {
         *current_stage.front().unwrap()
     }
 
-    /// Asses first item in the queue of items to be assessed and move to the
-    /// next. Call `self.current()` to get the next item.
-    pub fn update_current(&mut self, q: u8) {
+    /// Returns true if the current item is a due item which hasn't been
+    /// reviewed yet, i.e. it is neither new nor repeated.
+    pub fn is_current_review(&self) -> bool {
+        self.pending_reviews.contains(&self.current())
+    }
+
+    /// Asses first item in the queue of items to be assessed at the current
+    /// time of `clock` and move to the next. Call `self.current()` to get the
+    /// next item.
+    pub fn update_current(&mut self, q: u8, clock: &dyn Clock) {
         if self.is_done() {
             panic!("Unexpected update.");
         }
@@ -354,13 +750,37 @@ impl Schedule {
             self.refresh_stage.pop_front().unwrap()
         };
 
-        let item = self.items.get_mut(&item_id).unwrap();
+        self.pending_reviews.remove(&item_id);
+        let now = clock.now();
 
         if self.stage == 0 {
-            item.update(q);
-        }
+            self.changed.insert(item_id);
+            self.reviews.push(Review::new(item_id, now, q));
+            let calendar = self.config.calendar();
+            let today = calendar.date(now);
 
-        if q < 3 {
+            let interval = self
+                .items
+                .get_mut(&item_id)
+                .unwrap()
+                .update(q, now, &self.config);
+            if let Some(interval) = interval {
+                let interval = self.fuzz(item_id, today, interval);
+                let item = self.items.get_mut(&item_id).unwrap();
+                item.reschedule(today, interval, &calendar);
+            }
+
+            let item = &self.items[&item_id];
+            if item.next_revisit <= now {
+                self.hot_stage.push_back(item_id);
+            } else if item.is_learning() {
+                if calendar.date(item.next_revisit) == calendar.date(now) {
+                    self.enqueue_learning(item_id);
+                }
+            } else if q == 3 {
+                self.refresh_stage.push_back(item_id);
+            }
+        } else if q < 3 {
             self.hot_stage.push_back(item_id);
         } else if q == 3 {
             self.refresh_stage.push_back(item_id);
@@ -372,3 +792,355 @@ impl Schedule {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::storage::MemoryStorage;
+    use chrono::TimeZone;
+
+    fn config() -> Config {
+        let content = "timezone = UTC\n\
+                       learning_steps = 1m 10m\n\
+                       relearning_steps = 10m\n\
+                       learn_ahead = 1m\n\
+                       fuzz = 0";
+        Config::parse(content).unwrap()
+    }
+
+    /// Updates the item and reschedules it without any fuzz.
+    fn update(item: &mut ScheduleItem, q: u8, now: DateTime<Utc>, config: &Config) {
+        if let Some(interval) = item.update(q, now, config) {
+            let calendar = config.calendar();
+            item.reschedule(calendar.date(now), interval, &calendar);
+        }
+    }
+
+    #[test]
+    fn test_item_update() {
+        let config = config();
+        let now = Utc.ymd(2019, 3, 10).and_hms(8, 0, 0);
+        let mut item = ScheduleItem::new(now);
+
+        update(&mut item, 5, now, &config);
+        assert_eq!(item.phase, Phase::Learning(1));
+        assert_eq!(item.next_revisit, Utc.ymd(2019, 3, 10).and_hms(8, 10, 0));
+
+        let now = item.next_revisit;
+        update(&mut item, 1, now, &config);
+        assert_eq!(item.phase, Phase::Learning(0));
+        assert_eq!(item.next_revisit, Utc.ymd(2019, 3, 10).and_hms(8, 11, 0));
+
+        let now = item.next_revisit;
+        update(&mut item, 4, now, &config);
+        let now = item.next_revisit;
+        update(&mut item, 4, now, &config);
+        assert_eq!(item.phase, Phase::Review);
+        assert_eq!(item.iteration, 1);
+        assert_eq!(item.next_revisit, Utc.ymd(2019, 3, 11).and_hms(4, 0, 0));
+
+        let now = Utc.ymd(2019, 3, 11).and_hms(9, 0, 0);
+        update(&mut item, 4, now, &config);
+        assert_eq!(item.iteration, 2);
+        assert_eq!(item.last_revisit, now);
+        assert_eq!(item.next_revisit, Utc.ymd(2019, 3, 17).and_hms(4, 0, 0));
+
+        update(
+            &mut item,
+            5,
+            Utc.ymd(2019, 3, 17).and_hms(20, 0, 0),
+            &config,
+        );
+        assert_eq!(item.iteration, 3);
+        let interval = (item.ef * 6.0) as i64;
+        assert_eq!(
+            item.next_revisit,
+            Utc.ymd(2019, 3, 17).and_hms(4, 0, 0) + Duration::days(interval)
+        );
+
+        let now = item.next_revisit;
+        update(&mut item, 1, now, &config);
+        assert_eq!(item.iteration, 0);
+        assert_eq!(item.phase, Phase::Relearning(0));
+        assert_eq!(item.next_revisit, now + Duration::minutes(10));
+    }
+
+    #[test]
+    fn test_item_serialization() {
+        let calendar = config().calendar();
+        let (id, item) = ScheduleItem::deserialize(
+            "000000000000000a\t2019-03-11\t2019-03-10\t1\t2.6",
+            &calendar,
+        )
+        .unwrap();
+        assert_eq!(id, 10);
+        assert_eq!(item.phase, Phase::Review);
+        assert_eq!(item.next_revisit, Utc.ymd(2019, 3, 11).and_hms(4, 0, 0));
+        assert_eq!(
+            item.serialize(id),
+            "000000000000000a\t2019-03-11T04:00:00Z\t2019-03-10T04:00:00Z\t1\t2.6\treview\n"
+        );
+
+        let line =
+            "000000000000000b\t2019-03-11T08:10:00Z\t2019-03-11T08:00:00Z\t0\t2.5\trelearning:1";
+        let (id, item) = ScheduleItem::deserialize(line, &calendar).unwrap();
+        assert_eq!(item.phase, Phase::Relearning(1));
+        assert_eq!(item.serialize(id), format!("{}\n", line));
+    }
+
+    #[test]
+    fn test_learning_steps() {
+        let mut clock = FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(8, 0, 0));
+        let mut schedule = Schedule::new(&config());
+
+        schedule.add_item(1, &clock);
+        schedule.add_item(2, &clock);
+        assert_eq!(schedule.current(), 1);
+        schedule.update_current(0, &clock);
+        assert_eq!(schedule.current(), 2);
+        schedule.update_current(4, &clock);
+        assert!(schedule.is_done());
+        assert_eq!(schedule.learning_count(), 2);
+
+        // Nothing else to learn, item 1 is within learn ahead limit.
+        clock.set(Utc.ymd(2019, 3, 10).and_hms(8, 0, 30));
+        schedule.promote_learning(&clock);
+        assert_eq!(schedule.current(), 1);
+        schedule.update_current(5, &clock);
+        assert_eq!(schedule.learning_count(), 2);
+        assert!(schedule.is_done());
+
+        clock.set(Utc.ymd(2019, 3, 10).and_hms(8, 5, 0));
+        schedule.promote_learning(&clock);
+        assert!(schedule.is_done());
+        assert_eq!(
+            schedule.next_learning(),
+            Some(Utc.ymd(2019, 3, 10).and_hms(8, 10, 0))
+        );
+
+        clock.set(Utc.ymd(2019, 3, 10).and_hms(8, 10, 0));
+        schedule.promote_learning(&clock);
+        assert_eq!(schedule.current(), 2);
+        schedule.update_current(5, &clock);
+        assert_eq!(schedule.items[&2].phase, Phase::Review);
+        schedule.promote_learning(&clock);
+        assert_eq!(schedule.current(), 1);
+        schedule.update_current(5, &clock);
+        assert!(schedule.is_done());
+        assert_eq!(schedule.learning_count(), 0);
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let config = Config::parse("timezone = UTC\nfuzz = 0.1").unwrap();
+        let today = NaiveDate::from_ymd(2019, 3, 10);
+        let now = Utc.ymd(2019, 3, 10).and_hms(8, 0, 0);
+        let mut schedule = Schedule::new(&config);
+        for id in 0..20 {
+            schedule.items.insert(id, ScheduleItem::new(now));
+        }
+
+        let intervals: Vec<u32> = (0..20).map(|id| schedule.fuzz(id, today, 30)).collect();
+        assert!(intervals.iter().all(|days| (27..=33).contains(days)));
+        assert!(intervals.iter().any(|&days| days != intervals[0]));
+        assert_eq!(schedule.fuzz(7, today, 30), intervals[7]);
+        assert_eq!(schedule.fuzz(7, today, 2), 2);
+
+        // Even the largest fuzz doesn't schedule a review to the same day.
+        let config = Config::parse("timezone = UTC\nfuzz = 0.99").unwrap();
+        let mut schedule = Schedule::new(&config);
+        for id in 0..20 {
+            schedule.items.insert(id, ScheduleItem::new(now));
+        }
+        assert!((0..20).all(|id| (1..=6).contains(&schedule.fuzz(id, today, 3))));
+        assert!((0..20).all(|id| (1..=60).contains(&schedule.fuzz(id, today, 30))));
+    }
+
+    #[test]
+    fn test_load_balance() {
+        let config = Config::parse("timezone = UTC\nfuzz = 0.1\nload_balance = true").unwrap();
+        let calendar = config.calendar();
+        let today = NaiveDate::from_ymd(2019, 3, 10);
+        let now = Utc.ymd(2019, 3, 10).and_hms(8, 0, 0);
+        let mut schedule = Schedule::new(&config);
+
+        let mut load: FnvHashMap<u32, usize> = FnvHashMap::default();
+        for id in 0..9 {
+            schedule.items.insert(id, ScheduleItem::new(now));
+            let days = schedule.fuzz(id, today, 10);
+            *load.entry(days).or_insert(0) += 1;
+
+            let item = schedule.items.get_mut(&id).unwrap();
+            item.phase = Phase::Review;
+            item.reschedule(today, days, &calendar);
+        }
+
+        assert_eq!(load.len(), 3);
+        assert!((9..=11).all(|days| load[&days] == 3));
+    }
+
+    fn review_item(last: NaiveDate, next: NaiveDate, calendar: &Calendar) -> ScheduleItem {
+        let mut item = ScheduleItem::new(calendar.start(last));
+        item.phase = Phase::Review;
+        item.iteration = 2;
+        item.next_revisit = calendar.start(next);
+        item
+    }
+
+    #[test]
+    fn test_overdue_credit() {
+        let calendar = config().calendar();
+        let last = NaiveDate::from_ymd(2019, 3, 1);
+        let next = NaiveDate::from_ymd(2019, 3, 7);
+
+        let intervals: Vec<u32> = [5, 4, 3]
+            .iter()
+            .map(|&q| {
+                let mut item = review_item(last, next, &calendar);
+                item.graduate(q, NaiveDate::from_ymd(2019, 3, 19), &calendar)
+            })
+            .collect();
+        assert_eq!(intervals, vec![45, 30, 22]);
+
+        let mut item = review_item(last, next, &calendar);
+        assert_eq!(item.graduate(3, next, &calendar), 15);
+    }
+
+    #[test]
+    fn test_graduate_revisited_in_future() {
+        let calendar = config().calendar();
+        let last = NaiveDate::from_ymd(2019, 3, 10);
+        let next = NaiveDate::from_ymd(2019, 3, 16);
+
+        let mut item = review_item(last, next, &calendar);
+        let today = NaiveDate::from_ymd(2019, 3, 8);
+        assert_eq!(item.days_since(today, &calendar), 0);
+        assert_eq!(item.graduate(4, today, &calendar), 15);
+    }
+
+    #[test]
+    fn test_arrange_reviews() {
+        let config = Config::parse("timezone = UTC\nbacklog_per_day = 2").unwrap();
+        let calendar = config.calendar();
+        let date = |day| NaiveDate::from_ymd(2019, 3, day);
+        let mut schedule = Schedule::new(&config);
+
+        let reviews = [(1, 10, 20), (2, 18, 19), (3, 1, 11), (4, 14, 15)];
+        for &(id, last, next) in reviews.iter() {
+            let item = review_item(date(last), date(next), &calendar);
+            schedule.items.insert(id, item);
+            schedule.hot_stage.push_back(id);
+            schedule.pending_reviews.insert(id);
+        }
+        schedule
+            .items
+            .insert(5, ScheduleItem::new(calendar.start(date(19))));
+        schedule.hot_stage.push_back(5);
+
+        schedule.arrange_reviews(date(20));
+        assert_eq!(schedule.hot_stage, vec![5, 4, 2, 1]);
+        assert!(!schedule.pending_reviews.contains(&3));
+        assert!(schedule.pending_reviews.contains(&1));
+        assert_eq!(schedule.pending_reviews.len(), 3);
+    }
+
+    #[test]
+    fn test_limit_reviews() {
+        let config = config();
+        let calendar = config.calendar();
+        let date = |day| NaiveDate::from_ymd(2019, 3, day);
+        let mut schedule = Schedule::new(&config);
+
+        schedule
+            .items
+            .insert(4, ScheduleItem::new(calendar.start(date(10))));
+        schedule.hot_stage.push_back(4);
+        for id in 1..4 {
+            schedule
+                .items
+                .insert(id, review_item(date(1), date(10), &calendar));
+            schedule.hot_stage.push_back(id);
+            schedule.pending_reviews.insert(id);
+        }
+
+        schedule.limit_reviews(2);
+        assert_eq!(schedule.hot_stage, vec![4, 1, 2]);
+        assert_eq!(schedule.pending_reviews.len(), 2);
+        assert!(!schedule.pending_reviews.contains(&3));
+        assert!(schedule.has_item(3));
+
+        schedule.limit_reviews(0);
+        assert_eq!(schedule.hot_stage, vec![4]);
+        assert!(schedule.pending_reviews.is_empty());
+    }
+
+    #[test]
+    fn test_replay() {
+        let config = Config::parse("timezone = UTC\nlearning_steps = 1m\nfuzz = 0.2").unwrap();
+        let mut clock = FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(8, 0, 0));
+        let mut storage = MemoryStorage::new();
+        let mut schedule = Schedule::new(&config);
+        schedule.add_item(1, &clock);
+        schedule.update_current(5, &clock);
+        schedule.save(&mut storage).unwrap();
+
+        for &(month, day, q) in &[(3, 11, 4), (3, 20, 5), (5, 30, 3)] {
+            clock.set(Utc.ymd(2019, month, day).and_hms(8, 0, 0));
+            let mut schedule = Schedule::load(&storage, &config, &clock).unwrap();
+            assert_eq!(schedule.current(), 1);
+            schedule.update_current(q, &clock);
+            schedule.save(&mut storage).unwrap();
+        }
+
+        let reviews = storage.read_reviews().unwrap();
+        let stored = storage.read_schedule(&config.calendar()).unwrap();
+        let replayed = ScheduleItem::replay(None, 1, &reviews, &config).unwrap();
+        assert_eq!(stored, vec![(1, replayed)]);
+
+        let base = ScheduleItem::replay(None, 1, &reviews[..2], &config).unwrap();
+        let replayed = ScheduleItem::replay(Some(&base), 1, &reviews, &config).unwrap();
+        assert_eq!(stored, vec![(1, replayed)]);
+        assert_eq!(ScheduleItem::replay(None, 2, &reviews, &config), None);
+    }
+
+    #[test]
+    fn test_save() {
+        let clock = FixedClock::new(Utc.ymd(2019, 3, 10).and_hms(8, 0, 0));
+        let mut storage = MemoryStorage::new();
+        let mut schedule = Schedule::new(&config());
+        schedule.add_item(1, &clock);
+        schedule.add_item(2, &clock);
+        schedule.save(&mut storage).unwrap();
+
+        let mut schedule = Schedule::load(&storage, &config(), &clock).unwrap();
+        schedule.update_current(5, &clock);
+        schedule.add_item(3, &clock);
+
+        // Another process changes the stored schedule in the meantime.
+        let mut other = Schedule::load(&storage, &config(), &clock).unwrap();
+        other.add_item(4, &clock);
+        other.update_current(5, &clock);
+        other.update_current(0, &clock);
+        other.save(&mut storage).unwrap();
+        let stored = storage.read_schedule(&config().calendar()).unwrap();
+
+        schedule.save(&mut storage).unwrap();
+        let mut saved = storage.read_schedule(&config().calendar()).unwrap();
+        saved.sort_by_key(|&(id, _)| id);
+        let ids: Vec<u64> = saved.iter().map(|&(id, _)| id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(saved[0].1, schedule.items[&1]);
+        let other_item = |id| stored.iter().find(|&&(other, _)| other == id).unwrap();
+        assert_eq!(saved[1], *other_item(2));
+        assert_eq!(saved[3], *other_item(4));
+
+        let reviews: Vec<(u64, u8)> = storage
+            .read_reviews()
+            .unwrap()
+            .iter()
+            .map(|review| (review.card(), review.quality()))
+            .collect();
+        assert_eq!(reviews, vec![(1, 5), (2, 0), (1, 5)]);
+    }
+}
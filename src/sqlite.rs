@@ -0,0 +1,634 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::backup::Backup;
+use crate::calendar::Calendar;
+use crate::card::Card;
+use crate::error::Error;
+use crate::media::Media;
+use crate::progress::Progress;
+use crate::review::Review;
+use crate::scheduler::ScheduleItem;
+use crate::storage::Storage;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use fnv::{FnvHashMap, FnvHashSet};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+
+/// Version of the database schema stored in `user_version` of the database.
+const SCHEMA_VERSION: i64 = 1;
+
+/// Tables of the database. Cards are kept in order of insertion by their
+/// `position`. Times are stored as Unix timestamps, in seconds in schedule
+/// and in milliseconds in reviews.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS cards (
+        position INTEGER PRIMARY KEY,
+        id INTEGER NOT NULL UNIQUE,
+        question TEXT NOT NULL,
+        answer TEXT NOT NULL,
+        hint TEXT,
+        note INTEGER,
+        template INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS alternatives (
+        card INTEGER NOT NULL,
+        answer TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS alternatives_card ON alternatives (card);
+    CREATE TABLE IF NOT EXISTS media (
+        card INTEGER NOT NULL,
+        attribute TEXT NOT NULL,
+        file TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS media_card ON media (card);
+    CREATE TABLE IF NOT EXISTS siblings (
+        card INTEGER NOT NULL,
+        sibling INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS siblings_card ON siblings (card);
+    CREATE TABLE IF NOT EXISTS schedule (
+        card INTEGER PRIMARY KEY,
+        next_revisit INTEGER NOT NULL,
+        last_revisit INTEGER NOT NULL,
+        iteration INTEGER NOT NULL,
+        ef REAL NOT NULL,
+        phase TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS schedule_next_revisit ON schedule (next_revisit);
+    CREATE TABLE IF NOT EXISTS reviews (
+        card INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        quality INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS reviews_card ON reviews (card, time);
+    CREATE TABLE IF NOT EXISTS progress (
+        date TEXT NOT NULL,
+        new_cards INTEGER NOT NULL,
+        reviews INTEGER NOT NULL
+    );
+";
+
+/// Storage in a single SQLite database, suitable for large collections of
+/// cards. Only rows of changed cards and schedule items are written.
+pub struct SqliteStorage {
+    connection: Connection,
+    // Backup of VoLe directory made before the database is rewritten, `None`
+    // if the database is not stored in it.
+    backup: Option<Backup>,
+}
+
+impl SqliteStorage {
+    /// Opens database at `path` and creates it if it doesn't exist. VoLe
+    /// directory is backed up with `backup` before cards or schedule are
+    /// rewritten.
+    pub fn open(path: &Path, backup: Backup) -> Result<SqliteStorage, Error> {
+        let connection = Connection::open(path).map_err(Error::Sqlite)?;
+        SqliteStorage::init(connection, Some(backup))
+    }
+
+    /// Creates a new database kept in memory.
+    pub fn open_in_memory() -> Result<SqliteStorage, Error> {
+        SqliteStorage::init(Connection::open_in_memory().map_err(Error::Sqlite)?, None)
+    }
+
+    fn init(connection: Connection, backup: Option<Backup>) -> Result<SqliteStorage, Error> {
+        let mut storage = SqliteStorage { connection, backup };
+        let version: i64 = storage
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(Error::Sqlite)?;
+        if version > SCHEMA_VERSION {
+            return Err(Error::state(format!(
+                "The database is of schema version {}, this version of VoLe reads at most \
+                 version {}. Please upgrade VoLe.",
+                version, SCHEMA_VERSION
+            )));
+        }
+        if version < SCHEMA_VERSION {
+            storage.transaction(|transaction| {
+                transaction.execute_batch(SCHEMA)?;
+                transaction.pragma_update(None, "user_version", SCHEMA_VERSION)
+            })?;
+        }
+        Ok(storage)
+    }
+
+    /// Runs `operation` in a transaction which is committed only if the
+    /// operation succeeds.
+    fn transaction<F>(&mut self, operation: F) -> Result<(), Error>
+    where
+        F: FnOnce(&Transaction) -> rusqlite::Result<()>,
+    {
+        let transaction = self.connection.transaction().map_err(Error::Sqlite)?;
+        operation(&transaction).map_err(Error::Sqlite)?;
+        transaction.commit().map_err(Error::Sqlite)
+    }
+}
+
+/// Inserts `card` after all stored cards.
+fn insert_card(transaction: &Transaction, card: &Card) -> rusqlite::Result<()> {
+    let note = card.note().map(|(note, _)| note as i64);
+    let template = card.note().map(|(_, template)| template as i64);
+    transaction
+        .prepare_cached(
+            "INSERT INTO cards (id, question, answer, hint, note, template)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(params![
+            card.id() as i64,
+            card.question(),
+            card.answer(),
+            card.hint(),
+            note,
+            template
+        ])?;
+    insert_card_details(transaction, card)
+}
+
+/// Replaces stored card of the same ID with `card`, keeping its position.
+fn update_card(transaction: &Transaction, card: &Card) -> rusqlite::Result<()> {
+    let note = card.note().map(|(note, _)| note as i64);
+    let template = card.note().map(|(_, template)| template as i64);
+    transaction
+        .prepare_cached(
+            "UPDATE cards SET question = ?2, answer = ?3, hint = ?4, note = ?5, template = ?6
+             WHERE id = ?1",
+        )?
+        .execute(params![
+            card.id() as i64,
+            card.question(),
+            card.answer(),
+            card.hint(),
+            note,
+            template
+        ])?;
+    delete_card_details(transaction, card.id())?;
+    insert_card_details(transaction, card)
+}
+
+/// Inserts alternatives, media and siblings of `card`.
+fn insert_card_details(transaction: &Transaction, card: &Card) -> rusqlite::Result<()> {
+    let id = card.id() as i64;
+    let mut insert_alternative =
+        transaction.prepare_cached("INSERT INTO alternatives (card, answer) VALUES (?1, ?2)")?;
+    for alternative in card.alternatives() {
+        insert_alternative.execute(params![id, alternative])?;
+    }
+    let mut insert_media = transaction
+        .prepare_cached("INSERT INTO media (card, attribute, file) VALUES (?1, ?2, ?3)")?;
+    for media in card.media() {
+        let attribute = Media::attribute(media.kind(), media.side());
+        insert_media.execute(params![id, attribute, media.file()])?;
+    }
+    let mut insert_sibling =
+        transaction.prepare_cached("INSERT INTO siblings (card, sibling) VALUES (?1, ?2)")?;
+    for &sibling in card.siblings() {
+        insert_sibling.execute(params![id, sibling as i64])?;
+    }
+    Ok(())
+}
+
+/// Deletes alternatives, media and siblings of card with ID `id`.
+fn delete_card_details(transaction: &Transaction, id: u64) -> rusqlite::Result<()> {
+    for table in &["alternatives", "media", "siblings"] {
+        transaction
+            .prepare_cached(&format!("DELETE FROM {} WHERE card = ?1", table))?
+            .execute(params![id as i64])?;
+    }
+    Ok(())
+}
+
+/// Inserts schedule `item` of card with ID `id` or replaces its stored item.
+fn replace_schedule_item(
+    transaction: &Transaction,
+    id: u64,
+    item: &ScheduleItem,
+) -> rusqlite::Result<()> {
+    transaction
+        .prepare_cached(
+            "INSERT OR REPLACE INTO schedule
+             (card, next_revisit, last_revisit, iteration, ef, phase)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(params![
+            id as i64,
+            item.next_revisit().timestamp(),
+            item.last_revisit().timestamp(),
+            item.iteration(),
+            f64::from(item.ef()),
+            item.phase()
+        ])?;
+    Ok(())
+}
+
+fn replace_progress(transaction: &Transaction, progress: &Progress) -> rusqlite::Result<()> {
+    transaction.execute("DELETE FROM progress", [])?;
+    transaction.execute(
+        "INSERT INTO progress (date, new_cards, reviews) VALUES (?1, ?2, ?3)",
+        params![
+            progress.date().format("%Y-%m-%d").to_string(),
+            progress.new_cards() as i64,
+            progress.reviews() as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn timestamp(seconds: i64, field: &str) -> Result<DateTime<Utc>, Error> {
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .ok_or_else(|| Error::field(field, seconds.to_string()))
+}
+
+/// Alternative answers read from the database with indices of their cards.
+type Alternatives = Vec<(usize, String)>;
+
+/// Columns of a schedule row: card, next and last revisit, iteration, EF
+/// and phase.
+type ScheduleRow = (i64, i64, i64, u32, f64, String);
+
+impl Storage for SqliteStorage {
+    fn read_cards(&self) -> Result<Vec<Card>, Error> {
+        // Alternatives are returned aside, an invalid one is a VoLe error.
+        let read = || -> rusqlite::Result<(Vec<Card>, Alternatives)> {
+            let mut statement = self.connection.prepare(
+                "SELECT id, question, answer, hint, note, template
+                 FROM cards ORDER BY position",
+            )?;
+            let rows = statement.query_map([], |row| {
+                let mut card = Card::new(row.get::<_, i64>(0)? as u64, row.get(1)?, row.get(2)?);
+                if let Some(hint) = row.get(3)? {
+                    card.set_hint(hint);
+                }
+                if let (Some(note), Some(template)) =
+                    (row.get::<_, Option<i64>>(4)?, row.get::<_, Option<i64>>(5)?)
+                {
+                    card.set_note(note as u64, template as usize);
+                }
+                Ok(card)
+            })?;
+            let mut cards = rows.collect::<rusqlite::Result<Vec<Card>>>()?;
+
+            let indices: FnvHashMap<u64, usize> = cards
+                .iter()
+                .enumerate()
+                .map(|(index, card)| (card.id(), index))
+                .collect();
+
+            let mut alternatives = Vec::new();
+            let mut statement = self
+                .connection
+                .prepare("SELECT card, answer FROM alternatives ORDER BY rowid")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                if let Some(&index) = indices.get(&(row.get::<_, i64>(0)? as u64)) {
+                    alternatives.push((index, row.get(1)?));
+                }
+            }
+
+            let mut statement = self
+                .connection
+                .prepare("SELECT card, attribute, file FROM media ORDER BY rowid")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let attribute: String = row.get(1)?;
+                let index = indices.get(&(row.get::<_, i64>(0)? as u64));
+                if let (Some(&index), Some((kind, side))) =
+                    (index, Media::parse_attribute(&attribute))
+                {
+                    cards[index].add_media(Media::new(kind, side, row.get(2)?));
+                }
+            }
+
+            let mut statement = self
+                .connection
+                .prepare("SELECT card, sibling FROM siblings ORDER BY rowid")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                if let Some(&index) = indices.get(&(row.get::<_, i64>(0)? as u64)) {
+                    cards[index].add_sibling(row.get::<_, i64>(1)? as u64);
+                }
+            }
+
+            Ok((cards, alternatives))
+        };
+
+        let (mut cards, alternatives) = read().map_err(Error::Sqlite)?;
+        for (index, alternative) in alternatives {
+            cards[index].add_alternative(alternative)?;
+        }
+        Ok(cards)
+    }
+
+    fn store_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        self.transaction(|transaction| {
+            for card in cards {
+                insert_card(transaction, card)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rewrite_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        self.backup()?;
+        let stored = self.read_cards()?;
+        let stored_cards: FnvHashMap<u64, &Card> =
+            stored.iter().map(|card| (card.id(), card)).collect();
+        let kept: FnvHashSet<u64> = cards.iter().map(Card::id).collect();
+
+        // Cards keep their positions unless the order of the kept cards
+        // changes or a new card isn't stored after all of them.
+        let mut expected: Vec<u64> = stored
+            .iter()
+            .map(Card::id)
+            .filter(|id| kept.contains(id))
+            .collect();
+        expected.extend(
+            cards
+                .iter()
+                .map(Card::id)
+                .filter(|id| !stored_cards.contains_key(id)),
+        );
+        let reordered = cards.iter().map(Card::id).ne(expected);
+
+        self.transaction(|transaction| {
+            for card in stored.iter().filter(|card| !kept.contains(&card.id())) {
+                transaction
+                    .prepare_cached("DELETE FROM cards WHERE id = ?1")?
+                    .execute(params![card.id() as i64])?;
+                delete_card_details(transaction, card.id())?;
+            }
+            for card in cards {
+                match stored_cards.get(&card.id()) {
+                    Some(&stored) if stored == card => (),
+                    Some(_) => update_card(transaction, card)?,
+                    None => insert_card(transaction, card)?,
+                }
+            }
+            if reordered {
+                let mut update =
+                    transaction.prepare_cached("UPDATE cards SET position = ?2 WHERE id = ?1")?;
+                for (index, card) in cards.iter().enumerate() {
+                    update.execute(params![card.id() as i64, -(index as i64) - 1])?;
+                }
+                transaction.execute("UPDATE cards SET position = -position", [])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_schedule(&self, _calendar: &Calendar) -> Result<Vec<(u64, ScheduleItem)>, Error> {
+        let rows = || -> rusqlite::Result<Vec<ScheduleRow>> {
+            let mut statement = self.connection.prepare(
+                "SELECT card, next_revisit, last_revisit, iteration, ef, phase
+                 FROM schedule ORDER BY card",
+            )?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?;
+            rows.collect()
+        };
+
+        rows()
+            .map_err(Error::Sqlite)?
+            .into_iter()
+            .map(|(id, next_revisit, last_revisit, iteration, ef, phase)| {
+                let item = ScheduleItem::from_parts(
+                    timestamp(next_revisit, "next revisit")?,
+                    timestamp(last_revisit, "last revisit")?,
+                    iteration,
+                    ef as f32,
+                    &phase,
+                )?;
+                Ok((id as u64, item))
+            })
+            .collect()
+    }
+
+    fn write_schedule(&mut self, items: &[(u64, &ScheduleItem)]) -> Result<(), Error> {
+        self.backup()?;
+        let stored: FnvHashMap<u64, ScheduleItem> = self
+            .read_schedule(&Calendar::default())?
+            .into_iter()
+            .collect();
+        let kept: FnvHashSet<u64> = items.iter().map(|&(id, _)| id).collect();
+
+        self.transaction(|transaction| {
+            for id in stored.keys().filter(|id| !kept.contains(id)) {
+                transaction
+                    .prepare_cached("DELETE FROM schedule WHERE card = ?1")?
+                    .execute(params![*id as i64])?;
+            }
+            for &(id, item) in items {
+                if stored.get(&id) != Some(item) {
+                    replace_schedule_item(transaction, id, item)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn update_schedule(
+        &mut self,
+        items: &[(u64, &ScheduleItem)],
+        _calendar: &Calendar,
+    ) -> Result<(), Error> {
+        self.transaction(|transaction| {
+            for &(id, item) in items {
+                replace_schedule_item(transaction, id, item)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_reviews(&self) -> Result<Vec<Review>, Error> {
+        let rows = || -> rusqlite::Result<Vec<(i64, i64, u8)>> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT card, time, quality FROM reviews ORDER BY rowid")?;
+            let rows =
+                statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect()
+        };
+
+        rows()
+            .map_err(Error::Sqlite)?
+            .into_iter()
+            .map(|(card, time, quality)| {
+                let nanos = (time.rem_euclid(1000) * 1_000_000) as u32;
+                let time = Utc
+                    .timestamp_opt(time.div_euclid(1000), nanos)
+                    .single()
+                    .ok_or_else(|| Error::field("review time", time.to_string()))?;
+                Ok(Review::new(card as u64, time, quality))
+            })
+            .collect()
+    }
+
+    fn store_reviews(&mut self, reviews: &[Review]) -> Result<(), Error> {
+        self.transaction(|transaction| {
+            let mut insert = transaction
+                .prepare_cached("INSERT INTO reviews (card, time, quality) VALUES (?1, ?2, ?3)")?;
+            for review in reviews {
+                insert.execute(params![
+                    review.card() as i64,
+                    review.time().timestamp_millis(),
+                    review.quality()
+                ])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read_progress(&self) -> Result<Option<Progress>, Error> {
+        let row: Option<(String, i64, i64)> = self
+            .connection
+            .query_row("SELECT date, new_cards, reviews FROM progress", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()
+            .map_err(Error::Sqlite)?;
+        match row {
+            Some((date, new_cards, reviews)) => {
+                let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .map_err(|reason| Error::field_source("date", reason))?;
+                Ok(Some(Progress::from_counts(
+                    date,
+                    new_cards as usize,
+                    reviews as usize,
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_progress(&mut self, progress: &Progress) -> Result<(), Error> {
+        self.transaction(|transaction| replace_progress(transaction, progress))
+    }
+
+    fn backup(&mut self) -> Result<(), Error> {
+        match self.backup {
+            Some(ref mut backup) => backup.create(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::media::{MediaKind, Side};
+
+    #[test]
+    fn test_cards() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+
+        let mut first = Card::new(u64::MAX, "Hund".to_string(), "dog".to_string());
+        first.add_alternative("hound".to_string()).unwrap();
+        first.set_hint("animal".to_string());
+        first.add_media(Media::new(
+            MediaKind::Audio,
+            Side::Answer,
+            "dog.mp3".to_string(),
+        ));
+        let mut second = Card::new(2, "Katze".to_string(), "cat".to_string());
+        second.set_note(7, 1);
+        second.add_sibling(u64::MAX);
+
+        storage.store_cards(&[first.clone()]).unwrap();
+        storage.store_cards(&[second.clone()]).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), vec![first.clone(), second]);
+
+        storage.rewrite_cards(&[first.clone()]).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), vec![first]);
+    }
+
+    #[test]
+    fn test_rewrite_cards() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let cards: Vec<Card> = (1..=4)
+            .map(|id| Card::new(id, format!("question {}", id), "answer".to_string()))
+            .collect();
+        storage.store_cards(&cards).unwrap();
+
+        let mut changed = cards[2].clone();
+        changed.add_alternative("other".to_string()).unwrap();
+        let new = Card::new(5, "question 5".to_string(), "answer".to_string());
+        let rewritten = vec![cards[0].clone(), changed.clone(), new.clone()];
+        storage.rewrite_cards(&rewritten).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), rewritten);
+
+        let reordered = vec![new, cards[0].clone(), changed];
+        storage.rewrite_cards(&reordered).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), reordered);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let calendar = Calendar::default();
+        let line = "0000000000000001\t2019-03-12T04:00:00Z\t2019-03-10T08:00:00Z\t1\t2.36\treview";
+        let (id, item) = ScheduleItem::deserialize(line, &calendar).unwrap();
+        let other_line =
+            "0000000000000002\t2019-03-10T08:10:00Z\t2019-03-10T08:00:00Z\t0\t2.5\tlearning:1";
+        let (other_id, other) = ScheduleItem::deserialize(other_line, &calendar).unwrap();
+
+        storage.write_schedule(&[(id, &item)]).unwrap();
+        storage
+            .update_schedule(&[(other_id, &other)], &calendar)
+            .unwrap();
+        assert_eq!(
+            storage.read_schedule(&calendar).unwrap(),
+            vec![(id, item.clone()), (other_id, other)]
+        );
+
+        storage.write_schedule(&[(id, &item)]).unwrap();
+        assert_eq!(storage.read_schedule(&calendar).unwrap(), vec![(id, item)]);
+    }
+
+    #[test]
+    fn test_reviews() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let reviews = vec![
+            Review::new(2, Utc.ymd(2019, 3, 10).and_hms_milli(8, 0, 0, 250), 4),
+            Review::new(1, Utc.ymd(1969, 12, 31).and_hms_milli(23, 59, 59, 500), 0),
+        ];
+        storage.store_reviews(&reviews[..1]).unwrap();
+        storage.store_reviews(&reviews[1..]).unwrap();
+        assert_eq!(storage.read_reviews().unwrap(), reviews);
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(storage.read_progress().unwrap(), None);
+
+        let mut progress = Progress::new(NaiveDate::from_ymd(2019, 3, 10));
+        progress.add_review();
+        storage.write_progress(&progress).unwrap();
+        storage.write_progress(&progress).unwrap();
+        assert_eq!(storage.read_progress().unwrap(), Some(progress));
+    }
+}
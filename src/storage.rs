@@ -0,0 +1,329 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::backup::Backup;
+use crate::calendar::Calendar;
+use crate::card::Card;
+use crate::error::Error;
+use crate::file::{self, get_vole_dir, TextStorage, CARDS_FORMAT, SCHEDULE_FORMAT};
+use crate::progress::Progress;
+use crate::review::Review;
+use crate::scheduler::ScheduleItem;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStorage;
+use std::fs::{remove_file, rename};
+use std::path::{Path, PathBuf};
+
+/// File name of the SQLite database inside VoLe directory. Cards, schedule,
+/// reviews and progress are stored in the database instead of text files if it
+/// exists.
+const DATABASE_FILE_NAME: &str = "vole.sqlite";
+
+/// Persistent storage of cards, their schedule, log of reviews and learning
+/// progress. Text files in VoLe directory are the default storage, see
+/// `file::TextStorage`.
+pub trait Storage {
+    /// Reads all cards in the order they were stored.
+    fn read_cards(&self) -> Result<Vec<Card>, Error>;
+
+    /// Stores cards after all previously stored cards.
+    fn store_cards(&mut self, cards: &[Card]) -> Result<(), Error>;
+
+    /// Replaces all stored cards with `cards`.
+    fn rewrite_cards(&mut self, cards: &[Card]) -> Result<(), Error>;
+
+    /// Reads schedule items together with IDs of their cards. Dates stored
+    /// without time are interpreted as starts of learning days of
+    /// `calendar`.
+    fn read_schedule(&self, calendar: &Calendar) -> Result<Vec<(u64, ScheduleItem)>, Error>;
+
+    /// Replaces stored schedule with `items`.
+    fn write_schedule(&mut self, items: &[(u64, &ScheduleItem)]) -> Result<(), Error>;
+
+    /// Stores `items` in place of stored items of the same cards, other
+    /// stored items are kept. Dates stored without time are interpreted as
+    /// starts of learning days of `calendar`.
+    fn update_schedule(
+        &mut self,
+        items: &[(u64, &ScheduleItem)],
+        calendar: &Calendar,
+    ) -> Result<(), Error> {
+        let stored = self.read_schedule(calendar)?;
+        let mut merged: Vec<(u64, &ScheduleItem)> = stored
+            .iter()
+            .filter(|(id, _)| items.iter().all(|(other, _)| other != id))
+            .map(|(id, item)| (*id, item))
+            .collect();
+        merged.extend_from_slice(items);
+        self.write_schedule(&merged)
+    }
+
+    /// Reads the log of all reviews in the order they were stored.
+    fn read_reviews(&self) -> Result<Vec<Review>, Error>;
+
+    /// Stores reviews after all previously stored reviews.
+    fn store_reviews(&mut self, reviews: &[Review]) -> Result<(), Error>;
+
+    /// Reads the last stored progress, `None` if there is none.
+    fn read_progress(&self) -> Result<Option<Progress>, Error>;
+
+    /// Replaces stored progress with `progress`.
+    fn write_progress(&mut self, progress: &Progress) -> Result<(), Error>;
+
+    /// Backs up VoLe directory before the first change made through this
+    /// storage, later calls do nothing. Storages outside VoLe directory are
+    /// not backed up.
+    fn backup(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Storage keeping everything in memory, e.g. for tests or when VoLe is
+/// embedded into another application.
+#[derive(Default)]
+pub struct MemoryStorage {
+    cards: Vec<Card>,
+    schedule: Vec<(u64, ScheduleItem)>,
+    reviews: Vec<Review>,
+    progress: Option<Progress>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read_cards(&self) -> Result<Vec<Card>, Error> {
+        Ok(self.cards.clone())
+    }
+
+    fn store_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        self.cards.extend_from_slice(cards);
+        Ok(())
+    }
+
+    fn rewrite_cards(&mut self, cards: &[Card]) -> Result<(), Error> {
+        self.cards = cards.to_vec();
+        Ok(())
+    }
+
+    fn read_schedule(&self, _calendar: &Calendar) -> Result<Vec<(u64, ScheduleItem)>, Error> {
+        Ok(self.schedule.clone())
+    }
+
+    fn write_schedule(&mut self, items: &[(u64, &ScheduleItem)]) -> Result<(), Error> {
+        self.schedule = items.iter().map(|&(id, item)| (id, item.clone())).collect();
+        Ok(())
+    }
+
+    fn read_reviews(&self) -> Result<Vec<Review>, Error> {
+        Ok(self.reviews.clone())
+    }
+
+    fn store_reviews(&mut self, reviews: &[Review]) -> Result<(), Error> {
+        self.reviews.extend_from_slice(reviews);
+        Ok(())
+    }
+
+    fn read_progress(&self) -> Result<Option<Progress>, Error> {
+        Ok(self.progress.clone())
+    }
+
+    fn write_progress(&mut self, progress: &Progress) -> Result<(), Error> {
+        self.progress = Some(progress.clone());
+        Ok(())
+    }
+}
+
+/// Kind of storage used by VoLe directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Text,
+    Sqlite,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Backend, Error> {
+        match name {
+            "text" => Ok(Backend::Text),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(Error::field("storage backend", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Text => "text",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+
+    /// Returns backend currently used by VoLe directory.
+    pub fn current() -> Result<Backend, Error> {
+        if get_database_path()?.exists() {
+            Ok(Backend::Sqlite)
+        } else {
+            Ok(Backend::Text)
+        }
+    }
+}
+
+/// Opens storage currently used by VoLe directory.
+pub fn open() -> Result<Box<dyn Storage>, Error> {
+    open_backend(Backend::current()?, Backup::new())
+}
+
+fn open_backend(backend: Backend, backup: Backup) -> Result<Box<dyn Storage>, Error> {
+    match backend {
+        Backend::Text => Ok(Box::new(TextStorage::new(backup))),
+        Backend::Sqlite => open_database(&get_database_path()?, backup),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_database(path: &Path, backup: Backup) -> Result<Box<dyn Storage>, Error> {
+    Ok(Box::new(SqliteStorage::open(path, backup)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_database(_path: &Path, _backup: Backup) -> Result<Box<dyn Storage>, Error> {
+    Err(Error::state(
+        "SQLite storage is not supported, VoLe has to be built with feature \"sqlite\".",
+    ))
+}
+
+/// Moves all cards, their schedule, reviews and progress from the current
+/// storage to a storage of kind `to`. The former storage is backed up and
+/// removed afterwards. Dates stored without time are interpreted as starts of
+/// learning days of `calendar`.
+pub fn migrate(to: Backend, calendar: &Calendar) -> Result<(), Error> {
+    let from = Backend::current()?;
+    if from == to {
+        let reason = format!("VoLe already uses {} storage.", to.name());
+        return Err(Error::state(reason));
+    }
+    // The target storage shares the backup so that it doesn't back up the
+    // half-migrated state again.
+    let mut backup = Backup::new();
+    backup.create()?;
+
+    let source = open_backend(from, Backup::new())?;
+    let cards = source.read_cards()?;
+    let schedule = source.read_schedule(calendar)?;
+    let reviews = source.read_reviews()?;
+    let progress = source.read_progress()?;
+    drop(source);
+
+    let copy = |target: &mut dyn Storage| -> Result<(), Error> {
+        target.rewrite_cards(&cards)?;
+        let items: Vec<(u64, &ScheduleItem)> =
+            schedule.iter().map(|(id, item)| (*id, item)).collect();
+        target.write_schedule(&items)?;
+        target.store_reviews(&reviews)?;
+        match progress {
+            Some(ref progress) => target.write_progress(progress),
+            None => Ok(()),
+        }
+    };
+
+    let database_path = get_database_path()?;
+    match to {
+        Backend::Text => {
+            file::remove_text_storage()?;
+            copy(&mut TextStorage::new(backup))?;
+            remove_file(&database_path)
+                .map_err(|error| Error::io("remove file", &database_path, error))
+        }
+        Backend::Sqlite => {
+            // The database is written aside first so that it is never used
+            // half-full.
+            let tmp_path = database_path.with_extension("sqlite.tmp");
+            if tmp_path.exists() {
+                remove_file(&tmp_path)
+                    .map_err(|error| Error::io("remove file", &tmp_path, error))?;
+            }
+            copy(open_database(&tmp_path, backup)?.as_mut())?;
+            rename(&tmp_path, &database_path)
+                .map_err(|error| Error::io("rename file", &tmp_path, error))?;
+            file::remove_text_storage()
+        }
+    }
+}
+
+/// Upgrades cards and schedule files of text storage written by older
+/// versions of VoLe to the current format version; the files are backed up
+/// first. Returns names of the upgraded files and their former versions.
+/// Dates stored without time are interpreted as starts of learning days of
+/// `calendar`.
+pub fn upgrade(calendar: &Calendar) -> Result<Vec<(&'static str, u32)>, Error> {
+    if Backend::current()? != Backend::Text {
+        return Ok(Vec::new());
+    }
+
+    let mut storage = TextStorage::default();
+    let mut upgraded = Vec::new();
+
+    let version = CARDS_FORMAT.read_version()?;
+    if version < CARDS_FORMAT.version() {
+        let cards = storage.read_cards()?;
+        storage.rewrite_cards(&cards)?;
+        upgraded.push((CARDS_FORMAT.file_name(), version));
+    }
+
+    let version = SCHEDULE_FORMAT.read_version()?;
+    if version < SCHEDULE_FORMAT.version() {
+        let schedule = storage.read_schedule(calendar)?;
+        let items: Vec<(u64, &ScheduleItem)> =
+            schedule.iter().map(|(id, item)| (*id, item)).collect();
+        storage.write_schedule(&items)?;
+        upgraded.push((SCHEDULE_FORMAT.file_name(), version));
+    }
+
+    Ok(upgraded)
+}
+
+fn get_database_path() -> Result<PathBuf, Error> {
+    Ok(get_vole_dir()?.join(DATABASE_FILE_NAME))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new();
+
+        let cards = [
+            Card::new(1, "Hund".to_string(), "dog".to_string()),
+            Card::new(2, "Katze".to_string(), "cat".to_string()),
+        ];
+        storage.store_cards(&cards).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), cards.to_vec());
+
+        storage.rewrite_cards(&cards[..1]).unwrap();
+        assert_eq!(storage.read_cards().unwrap(), cards[..1].to_vec());
+
+        assert_eq!(storage.read_progress().unwrap(), None);
+        let progress = Progress::new(NaiveDate::from_ymd(2019, 3, 10));
+        storage.write_progress(&progress).unwrap();
+        assert_eq!(storage.read_progress().unwrap(), Some(progress));
+    }
+}
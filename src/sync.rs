@@ -0,0 +1,860 @@
+// Copyright (C) 2019  Martin Indra
+//
+// This file is part of VoLe.
+//
+// VoLe is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::calendar::Calendar;
+use crate::card::Card;
+use crate::config::Config;
+use crate::error::Error;
+use crate::file::{self, get_vole_dir};
+use crate::note::Note;
+use crate::review::Review;
+use crate::scheduler::ScheduleItem;
+use crate::storage::Storage;
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::BTreeSet;
+use std::fs::read_to_string;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::slice;
+use std::time::Duration;
+
+/// First line of a serialized snapshot identifying its format.
+const HEADER: &str = "vole-sync 2";
+/// First line of serialized changes sent by a client.
+const CHANGES_HEADER: &str = "vole-sync-changes 2";
+/// File name of the file inside VoLe directory where sync server keeps the
+/// merged snapshot of all its clients and IDs of deleted cards and notes.
+const SERVER_FILE_NAME: &str = "sync.txt";
+/// File name of the file inside VoLe directory where a client keeps the
+/// snapshot received during its last sync.
+const BASE_FILE_NAME: &str = "sync-base.txt";
+/// Time after which a stalled connection is closed.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum length of changes received by sync server in bytes.
+const MAX_REQUEST_LENGTH: u64 = 256 * 1024 * 1024;
+
+/// Cards, notes, schedule and reviews of a device exchanged during sync.
+/// Progress is not synced, daily limits apply to each device separately.
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    cards: Vec<Card>,
+    notes: Vec<Note>,
+    schedule: Vec<(u64, ScheduleItem)>,
+    reviews: Vec<Review>,
+}
+
+/// Change of a single record, e.g. a card, made by a client since its last
+/// sync. `old` is the record as received during the last sync, `new` is the
+/// current record; `None` if the record was added or deleted respectively.
+#[derive(Debug, PartialEq)]
+struct Change<T> {
+    old: Option<T>,
+    new: Option<T>,
+}
+
+/// Changes of cards, notes and schedule and new reviews made by a client
+/// since its last sync.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    cards: Vec<Change<Card>>,
+    notes: Vec<Change<Note>>,
+    schedule: Vec<Change<(u64, ScheduleItem)>>,
+    reviews: Vec<Review>,
+}
+
+/// State of sync server: snapshot merged from all clients and IDs of deleted
+/// cards and notes, so that a deletion is not undone by a client which
+/// hasn't synced since.
+#[derive(Debug, Default, PartialEq)]
+struct Server {
+    snapshot: Snapshot,
+    deleted_cards: BTreeSet<u64>,
+    deleted_notes: BTreeSet<u64>,
+}
+
+impl Snapshot {
+    /// Loads cards, schedule and reviews from `storage` and notes from notes
+    /// file. Dates stored without time are interpreted as starts of learning
+    /// days of `calendar`.
+    pub fn load(storage: &dyn Storage, calendar: &Calendar) -> Result<Snapshot, Error> {
+        Ok(Snapshot {
+            cards: storage.read_cards()?,
+            notes: file::read_notes()?,
+            schedule: storage.read_schedule(calendar)?,
+            reviews: storage.read_reviews()?,
+        })
+    }
+
+    /// Replaces cards and schedule in `storage` and notes in notes file with
+    /// content of the snapshot. Reviews of the snapshot missing in `storage`
+    /// are stored after the stored ones.
+    pub fn save(&self, storage: &mut dyn Storage) -> Result<(), Error> {
+        storage.backup()?;
+        storage.rewrite_cards(&self.cards)?;
+        file::rewrite_notes(&self.notes)?;
+        let items: Vec<(u64, &ScheduleItem)> =
+            self.schedule.iter().map(|(id, item)| (*id, item)).collect();
+        storage.write_schedule(&items)?;
+
+        let stored: FnvHashSet<Review> = storage.read_reviews()?.into_iter().collect();
+        let missing: Vec<Review> = self
+            .reviews
+            .iter()
+            .filter(|review| !stored.contains(review))
+            .cloned()
+            .collect();
+        storage.store_reviews(&missing)
+    }
+
+    /// Returns changes which turn snapshot `base` into this snapshot.
+    pub fn changes_since(&self, base: &Snapshot) -> Changes {
+        let reviews: FnvHashSet<&Review> = base.reviews.iter().collect();
+        Changes {
+            cards: diff(&base.cards, &self.cards, Card::id),
+            notes: diff(&base.notes, &self.notes, Note::id),
+            schedule: diff(&base.schedule, &self.schedule, |&(id, _)| id),
+            reviews: self
+                .reviews
+                .iter()
+                .filter(|review| !reviews.contains(review))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Serialize the snapshot to a header line followed by sections of
+    /// cards, notes, schedule and reviews lines. Each section starts with a
+    /// line of its name and number of its lines.
+    pub fn serialize(&self) -> String {
+        let mut content = format!("{}\n", HEADER);
+        write_section(&mut content, "cards", &self.cards, Card::to_line);
+        write_section(&mut content, "notes", &self.notes, Note::to_line);
+        write_section(&mut content, "schedule", &self.schedule, |(id, item)| {
+            item.serialize(*id)
+        });
+        write_section(&mut content, "reviews", &self.reviews, Review::serialize);
+        content
+    }
+
+    /// Parse a snapshot serialized by `serialize`.
+    pub fn deserialize(content: &str, calendar: &Calendar) -> Result<Snapshot, Error> {
+        let mut lines = content.lines().enumerate();
+        let snapshot = Snapshot::parse(&mut lines, calendar)?;
+        match lines.next() {
+            Some((i, line)) => {
+                let reason = format!("Unexpected line: {}", line);
+                Err(Error::parse(reason).at_line(i + 1))
+            }
+            None => Ok(snapshot),
+        }
+    }
+
+    /// Parses a snapshot from the beginning of `lines`.
+    fn parse<'a, I>(lines: &mut I, calendar: &Calendar) -> Result<Snapshot, Error>
+    where
+        I: Iterator<Item = (usize, &'a str)>,
+    {
+        match lines.next() {
+            Some((_, HEADER)) => (),
+            _ => return Err(Error::parse("Not a VoLe sync snapshot.")),
+        }
+
+        let mut snapshot = Snapshot::default();
+        for (i, line) in section(lines, "cards")? {
+            let card = Card::from_line(line).map_err(|error| error.at_line(i + 1))?;
+            snapshot.cards.push(card);
+        }
+        for (i, line) in section(lines, "notes")? {
+            let note = Note::from_line(line).map_err(|error| error.at_line(i + 1))?;
+            snapshot.notes.push(note);
+        }
+        for (i, line) in section(lines, "schedule")? {
+            let item =
+                ScheduleItem::deserialize(line, calendar).map_err(|error| error.at_line(i + 1))?;
+            snapshot.schedule.push(item);
+        }
+        for (i, line) in section(lines, "reviews")? {
+            let review = Review::deserialize(line).map_err(|error| error.at_line(i + 1))?;
+            snapshot.reviews.push(review);
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl Changes {
+    /// Serialize the changes to a header line followed by sections of cards,
+    /// notes, schedule and reviews. Each section starts with a line of its
+    /// name and number of changes; a change consists of a line of the old
+    /// and a line of the new record, `-` stands for no record.
+    pub fn serialize(&self) -> String {
+        let mut content = format!("{}\n", CHANGES_HEADER);
+        write_changes(&mut content, "cards", &self.cards, Card::to_line);
+        write_changes(&mut content, "notes", &self.notes, Note::to_line);
+        write_changes(&mut content, "schedule", &self.schedule, |(id, item)| {
+            item.serialize(*id)
+        });
+        write_section(&mut content, "reviews", &self.reviews, Review::serialize);
+        content
+    }
+
+    /// Parse changes serialized by `serialize`.
+    pub fn deserialize(content: &str, calendar: &Calendar) -> Result<Changes, Error> {
+        let mut lines = content.lines().enumerate();
+        match lines.next() {
+            Some((_, CHANGES_HEADER)) => (),
+            _ => return Err(Error::parse("Not VoLe sync changes.")),
+        }
+
+        let changes = Changes {
+            cards: parse_changes(&mut lines, "cards", Card::from_line)?,
+            notes: parse_changes(&mut lines, "notes", Note::from_line)?,
+            schedule: parse_changes(&mut lines, "schedule", |line| {
+                ScheduleItem::deserialize(line, calendar)
+            })?,
+            reviews: section(&mut lines, "reviews")?
+                .into_iter()
+                .map(|(i, line)| Review::deserialize(line).map_err(|error| error.at_line(i + 1)))
+                .collect::<Result<Vec<Review>, Error>>()?,
+        };
+        Ok(changes)
+    }
+}
+
+impl Server {
+    /// Applies changes of a client. A change is applied as it is if the
+    /// record hasn't been changed by another client since the client's last
+    /// sync. Otherwise a card or note added by the client is kept as it is
+    /// on the server and a card or note edited or deleted by the client is
+    /// taken from the client. A schedule item changed by both is recomputed
+    /// from all reviews of the card made since the client's last sync with
+    /// `config`; without such reviews the item revisited later wins.
+    fn apply(&mut self, changes: Changes, config: &Config) {
+        let snapshot = &mut self.snapshot;
+        apply_records(
+            &mut snapshot.cards,
+            &mut self.deleted_cards,
+            changes.cards,
+            Card::id,
+        );
+        apply_records(
+            &mut snapshot.notes,
+            &mut self.deleted_notes,
+            changes.notes,
+            Note::id,
+        );
+
+        let reviews: FnvHashSet<Review> = snapshot.reviews.iter().cloned().collect();
+        snapshot.reviews.extend(
+            changes
+                .reviews
+                .into_iter()
+                .filter(|review| !reviews.contains(review)),
+        );
+
+        let reviews = &snapshot.reviews;
+        apply(
+            &mut snapshot.schedule,
+            changes.schedule,
+            |&(id, _)| id,
+            |current, change| {
+                let (id, new) = match change.new {
+                    Some(new) => new,
+                    None => return current,
+                };
+                let base = change.old.as_ref().map(|(_, item)| item);
+                let item = match (ScheduleItem::replay(base, id, reviews, config), current) {
+                    (Some(item), _) => item,
+                    (None, Some((_, current))) if current.last_revisit() > new.last_revisit() => {
+                        current
+                    }
+                    (None, _) => new,
+                };
+                Some((id, item))
+            },
+        );
+
+        let cards: FnvHashSet<u64> = snapshot.cards.iter().map(Card::id).collect();
+        snapshot.schedule.retain(|(id, _)| cards.contains(id));
+    }
+
+    /// Serialize the server state to the snapshot followed by sections of IDs
+    /// of deleted cards and notes.
+    fn serialize(&self) -> String {
+        let mut content = self.snapshot.serialize();
+        let deleted_cards: Vec<u64> = self.deleted_cards.iter().cloned().collect();
+        write_section(&mut content, "deleted-cards", &deleted_cards, |&id| {
+            format!("{}\n", Card::serialize_id(id))
+        });
+        let deleted_notes: Vec<u64> = self.deleted_notes.iter().cloned().collect();
+        write_section(&mut content, "deleted-notes", &deleted_notes, |&id| {
+            format!("{}\n", Card::serialize_id(id))
+        });
+        content
+    }
+
+    /// Parse server state serialized by `serialize`.
+    fn deserialize(content: &str, calendar: &Calendar) -> Result<Server, Error> {
+        let mut lines = content.lines().enumerate();
+        let snapshot = Snapshot::parse(&mut lines, calendar)?;
+
+        let mut ids = |name| -> Result<BTreeSet<u64>, Error> {
+            section(&mut lines, name)?
+                .into_iter()
+                .map(|(i, line)| Card::parse_id(line).map_err(|error| error.at_line(i + 1)))
+                .collect()
+        };
+        Ok(Server {
+            snapshot,
+            deleted_cards: ids("deleted-cards")?,
+            deleted_notes: ids("deleted-notes")?,
+        })
+    }
+}
+
+/// Returns changes of records identified by `id` which turn `base` into
+/// `records`.
+fn diff<T, F>(base: &[T], records: &[T], id: F) -> Vec<Change<T>>
+where
+    T: Clone + PartialEq,
+    F: Fn(&T) -> u64,
+{
+    let base_records: FnvHashMap<u64, &T> =
+        base.iter().map(|record| (id(record), record)).collect();
+    let ids: FnvHashSet<u64> = records.iter().map(&id).collect();
+
+    let mut changes: Vec<Change<T>> = records
+        .iter()
+        .filter(|record| base_records.get(&id(record)) != Some(record))
+        .map(|record| Change {
+            old: base_records.get(&id(record)).map(|&old| old.clone()),
+            new: Some(record.clone()),
+        })
+        .collect();
+    changes.extend(
+        base.iter()
+            .filter(|record| !ids.contains(&id(record)))
+            .map(|record| Change {
+                old: Some(record.clone()),
+                new: None,
+            }),
+    );
+    changes
+}
+
+/// Applies `changes` to `records` identified by `id`. A change is applied as
+/// it is if the record equals the old record of the change, otherwise
+/// `resolve` is given the current record and the change and returns the
+/// record to keep, `None` to delete it. Added records are appended.
+fn apply<T, I, R>(records: &mut Vec<T>, changes: Vec<Change<T>>, id: I, mut resolve: R)
+where
+    T: PartialEq,
+    I: Fn(&T) -> u64,
+    R: FnMut(Option<T>, Change<T>) -> Option<T>,
+{
+    let mut slots: Vec<Option<T>> = records.drain(..).map(Some).collect();
+    let mut indices: FnvHashMap<u64, usize> = slots
+        .iter()
+        .enumerate()
+        .filter_map(|(index, record)| record.as_ref().map(|record| (id(record), index)))
+        .collect();
+
+    for change in changes {
+        let key = match change.new.as_ref().or(change.old.as_ref()) {
+            Some(record) => id(record),
+            None => continue,
+        };
+        let index = *indices.entry(key).or_insert_with(|| {
+            slots.push(None);
+            slots.len() - 1
+        });
+        let current = slots[index].take();
+        slots[index] = if current == change.old {
+            change.new
+        } else {
+            resolve(current, change)
+        };
+    }
+
+    records.extend(slots.into_iter().flatten());
+}
+
+/// Applies changes of cards or notes, see `Server::apply`. IDs of deleted
+/// records are kept in `deleted` and records with these IDs are never added
+/// again, only an edit of a deleted record restores it.
+fn apply_records<T, I>(
+    records: &mut Vec<T>,
+    deleted: &mut BTreeSet<u64>,
+    changes: Vec<Change<T>>,
+    id: I,
+) where
+    T: PartialEq,
+    I: Fn(&T) -> u64,
+{
+    let changes: Vec<Change<T>> = changes
+        .into_iter()
+        .filter(|change| match (&change.old, &change.new) {
+            (None, Some(new)) => !deleted.contains(&id(new)),
+            _ => true,
+        })
+        .collect();
+    let removed: Vec<u64> = changes
+        .iter()
+        .filter(|change| change.new.is_none())
+        .filter_map(|change| change.old.as_ref().map(&id))
+        .collect();
+
+    apply(records, changes, &id, |current, change| match change.old {
+        Some(_) => change.new,
+        None => current,
+    });
+
+    let ids: FnvHashSet<u64> = records.iter().map(&id).collect();
+    deleted.extend(removed);
+    deleted.retain(|id| !ids.contains(id));
+}
+
+/// Appends section `name` of `records` serialized by `line` to `content`.
+fn write_section<T, F>(content: &mut String, name: &str, records: &[T], line: F)
+where
+    F: Fn(&T) -> String,
+{
+    content.push_str(&format!("{} {}\n", name, records.len()));
+    for record in records {
+        content.push_str(&line(record));
+    }
+}
+
+/// Appends section `name` of `changes` of records serialized by `line` to
+/// `content`.
+fn write_changes<T, F>(content: &mut String, name: &str, changes: &[Change<T>], line: F)
+where
+    F: Fn(&T) -> String,
+{
+    content.push_str(&format!("{} {}\n", name, changes.len()));
+    for change in changes {
+        for record in &[&change.old, &change.new] {
+            match record {
+                Some(record) => content.push_str(&line(record)),
+                None => content.push_str("-\n"),
+            }
+        }
+    }
+}
+
+/// Parses section `name` of changes of records parsed by `parse`.
+fn parse_changes<'a, T, I, P>(lines: &mut I, name: &str, parse: P) -> Result<Vec<Change<T>>, Error>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+    P: Fn(&str) -> Result<T, Error>,
+{
+    let parse = |&(i, line): &(usize, &str)| -> Result<Option<T>, Error> {
+        match line {
+            "-" => Ok(None),
+            line => parse(line).map(Some).map_err(|error| error.at_line(i + 1)),
+        }
+    };
+
+    let incomplete = || Error::parse(format!("Section {} is incomplete.", name));
+    let count = section_length(lines, name)?
+        .checked_mul(2)
+        .ok_or_else(incomplete)?;
+    let lines: Vec<(usize, &str)> = lines.take(count).collect();
+    if lines.len() < count {
+        return Err(incomplete());
+    }
+    lines
+        .chunks(2)
+        .map(|pair| {
+            Ok(Change {
+                old: parse(&pair[0])?,
+                new: parse(&pair[1])?,
+            })
+        })
+        .collect()
+}
+
+/// Returns numbered lines of section `name` which is the next section in
+/// `lines`.
+fn section<'a, I>(lines: &mut I, name: &str) -> Result<Vec<(usize, &'a str)>, Error>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let count = section_length(lines, name)?;
+    let section: Vec<(usize, &str)> = lines.take(count).collect();
+    if section.len() < count {
+        let reason = format!("Section {} is incomplete.", name);
+        return Err(Error::parse(reason));
+    }
+    Ok(section)
+}
+
+/// Parses the first line of section `name` which is the next section in
+/// `lines` and returns the length of the section.
+fn section_length<'a, I>(lines: &mut I, name: &str) -> Result<usize, Error>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let (i, line) = match lines.next() {
+        Some(line) => line,
+        None => return Err(Error::parse(format!("Missing section {}.", name))),
+    };
+    match line
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix(' '))
+    {
+        Some(count) => count
+            .parse::<usize>()
+            .map_err(|reason| Error::field_source("section length", reason).at_line(i + 1)),
+        None => {
+            let reason = format!("Expected section {}, got: {}", name, line);
+            Err(Error::parse(reason).at_line(i + 1))
+        }
+    }
+}
+
+/// Sends changes of local cards, notes and schedule and new reviews since
+/// the last sync to sync server at `address` and replaces local cards, notes
+/// and schedule with the merged snapshot returned by the server. Reviews made
+/// on other devices are added to the local ones.
+pub fn sync(address: &str, storage: &mut dyn Storage, calendar: &Calendar) -> Result<(), Error> {
+    let local = Snapshot::load(storage, calendar)?;
+    let base_path = get_vole_dir()?.join(BASE_FILE_NAME);
+    let base = read_snapshot(&base_path, calendar)?;
+
+    let mut stream = TcpStream::connect(address)
+        .map_err(|error| Error::network("connect to", address, error))?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .and_then(|_| stream.write_all(local.changes_since(&base).serialize().as_bytes()))
+        .and_then(|_| stream.shutdown(Shutdown::Write))
+        .map_err(|error| Error::network("send to", address, error))?;
+
+    let mut content = String::new();
+    stream
+        .read_to_string(&mut content)
+        .map_err(|error| Error::network("receive from", address, error))?;
+    Snapshot::deserialize(&content, calendar)?.save(storage)?;
+    file::rewrite_lines(&base_path, slice::from_ref(&content))
+}
+
+/// Reads snapshot stored at `path`, an empty snapshot if there is no file.
+fn read_snapshot(path: &Path, calendar: &Calendar) -> Result<Snapshot, Error> {
+    match read_to_string(path) {
+        Ok(content) => Snapshot::deserialize(&content, calendar),
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(Snapshot::default()),
+        Err(error) => Err(Error::io("read file", path, error)),
+    }
+}
+
+/// Runs sync server on `address` until it is killed. Changes of clients are
+/// merged one by one into server snapshot stored in VoLe directory, see
+/// `Server::apply` for resolution of conflicting changes. Schedule is
+/// recomputed according to `config`.
+pub fn serve(address: &str, config: &Config) -> Result<(), Error> {
+    let listener =
+        TcpListener::bind(address).map_err(|error| Error::network("listen on", address, error))?;
+    if let Ok(address) = listener.local_addr() {
+        println!("Listening on {}.", address);
+    }
+
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(|error| Error::network("accept client on", address, error))
+            .and_then(|stream| serve_client(stream, config));
+        if let Err(reason) = result {
+            eprintln!("{}", reason);
+        }
+    }
+
+    Ok(())
+}
+
+fn serve_client(mut stream: TcpStream, config: &Config) -> Result<(), Error> {
+    let client = match stream.peer_addr() {
+        Ok(address) => address.to_string(),
+        Err(_) => String::from("client"),
+    };
+    let calendar = config.calendar();
+
+    let mut content = String::new();
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .and_then(|_| {
+            Read::by_ref(&mut stream)
+                .take(MAX_REQUEST_LENGTH + 1)
+                .read_to_string(&mut content)
+        })
+        .map_err(|error| Error::network("receive from", &client, error))?;
+    if content.len() as u64 > MAX_REQUEST_LENGTH {
+        let reason = format!("Changes sent by {} are too large.", client);
+        return Err(Error::state(reason));
+    }
+    let changes = Changes::deserialize(&content, &calendar)?;
+
+    let path = get_vole_dir()?.join(SERVER_FILE_NAME);
+    let mut server = match read_to_string(&path) {
+        Ok(content) => Server::deserialize(&content, &calendar)?,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => Server::default(),
+        Err(error) => return Err(Error::io("read file", &path, error)),
+    };
+    server.apply(changes, config);
+    file::rewrite_lines(&path, slice::from_ref(&server.serialize()))?;
+
+    stream
+        .write_all(server.snapshot.serialize().as_bytes())
+        .map_err(|error| Error::network("send to", &client, error))?;
+    println!("Synced {}.", client);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calendar::Timezone;
+    use chrono::{TimeZone, Utc};
+
+    fn calendar() -> Calendar {
+        Calendar::new(4, Timezone::parse("UTC").unwrap())
+    }
+
+    fn config() -> Config {
+        Config::parse("timezone = UTC\nday_start = 4\nfuzz = 0").unwrap()
+    }
+
+    fn card(id: u64, answer: &str) -> Card {
+        Card::new(id, format!("question {}", id), answer.to_string())
+    }
+
+    fn item(id: u64, last_revisit: &str) -> (u64, ScheduleItem) {
+        let line = format!(
+            "{}\t2019-03-12T04:00:00Z\t{}\t1\t2.5\treview",
+            Card::serialize_id(id),
+            last_revisit
+        );
+        ScheduleItem::deserialize(&line, &calendar()).unwrap()
+    }
+
+    fn answers(snapshot: &Snapshot) -> Vec<&str> {
+        snapshot.cards.iter().map(Card::answer).collect()
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut note = Note::new(3, "basic".to_string());
+        note.set_field("front", "Hund".to_string());
+        let snapshot = Snapshot {
+            cards: vec![Card::new(1, "Hund".to_string(), "dog".to_string())],
+            notes: vec![note],
+            schedule: vec![item(1, "2019-03-10T08:00:00Z")],
+            reviews: vec![Review::new(1, Utc.ymd(2019, 3, 10).and_hms(8, 0, 0), 4)],
+        };
+
+        let content = snapshot.serialize();
+        assert!(content.starts_with("vole-sync 2\ncards 1\n"));
+        assert_eq!(
+            Snapshot::deserialize(&content, &calendar()).unwrap(),
+            snapshot
+        );
+
+        let error = Snapshot::deserialize("vole-sync 2\ncards 2\n", &calendar()).unwrap_err();
+        assert_eq!(error.to_string(), "Section cards is incomplete.");
+
+        assert!(Snapshot::deserialize("vole-sync 1\ncards 0\n", &calendar()).is_err());
+
+        let server = Server {
+            snapshot,
+            deleted_cards: vec![2].into_iter().collect(),
+            ..Server::default()
+        };
+        assert_eq!(
+            Server::deserialize(&server.serialize(), &calendar()).unwrap(),
+            server
+        );
+        assert!(Server::deserialize(&server.snapshot.serialize(), &calendar()).is_err());
+    }
+
+    #[test]
+    fn test_changes() {
+        let base = Snapshot {
+            cards: vec![card(1, "dog"), card(2, "cat"), card(3, "mouse")],
+            reviews: vec![Review::new(1, Utc.ymd(2019, 3, 10).and_hms(8, 0, 0), 4)],
+            ..Snapshot::default()
+        };
+        let local = Snapshot {
+            cards: vec![card(1, "dog"), card(3, "rat"), card(4, "fish")],
+            reviews: vec![
+                Review::new(1, Utc.ymd(2019, 3, 10).and_hms(8, 0, 0), 4),
+                Review::new(3, Utc.ymd(2019, 3, 11).and_hms(8, 0, 0), 5),
+            ],
+            ..Snapshot::default()
+        };
+
+        let changes = local.changes_since(&base);
+        assert_eq!(
+            changes.cards,
+            vec![
+                Change {
+                    old: Some(card(3, "mouse")),
+                    new: Some(card(3, "rat")),
+                },
+                Change {
+                    old: None,
+                    new: Some(card(4, "fish")),
+                },
+                Change {
+                    old: Some(card(2, "cat")),
+                    new: None,
+                },
+            ]
+        );
+        assert_eq!(changes.reviews, local.reviews[1..].to_vec());
+
+        let content = changes.serialize();
+        assert!(content.starts_with("vole-sync-changes 2\ncards 3\n"));
+        assert_eq!(
+            Changes::deserialize(&content, &calendar()).unwrap(),
+            changes
+        );
+        assert!(Changes::deserialize("vole-sync 1\ncards 0\n", &calendar()).is_err());
+        let content = format!("vole-sync-changes 2\ncards {}\n", usize::MAX);
+        let error = Changes::deserialize(&content, &calendar()).unwrap_err();
+        assert_eq!(error.to_string(), "Section cards is incomplete.");
+    }
+
+    #[test]
+    fn test_apply() {
+        let base = Snapshot {
+            cards: vec![card(1, "dog"), card(2, "cat"), card(3, "mouse")],
+            ..Snapshot::default()
+        };
+        let mut server = Server {
+            snapshot: Snapshot {
+                cards: base.cards.clone(),
+                ..Snapshot::default()
+            },
+            ..Server::default()
+        };
+
+        // The first client edits a card and deletes another one.
+        let first = Snapshot {
+            cards: vec![card(1, "hound"), card(3, "mouse")],
+            ..Snapshot::default()
+        };
+        server.apply(first.changes_since(&base), &config());
+        assert_eq!(answers(&server.snapshot), vec!["hound", "mouse"]);
+
+        // The second client edits another card and the same card; cards it
+        // didn't change are not overwritten and the deleted card isn't
+        // restored.
+        let second = Snapshot {
+            cards: vec![card(1, "puppy"), card(2, "cat"), card(3, "rat")],
+            ..Snapshot::default()
+        };
+        server.apply(second.changes_since(&base), &config());
+        assert_eq!(answers(&server.snapshot), vec!["puppy", "rat"]);
+        assert_eq!(server.deleted_cards, vec![2].into_iter().collect());
+
+        // A deleted card is never added again.
+        let third = Snapshot {
+            cards: vec![card(2, "cat"), card(4, "fish")],
+            ..Snapshot::default()
+        };
+        server.apply(third.changes_since(&Snapshot::default()), &config());
+        assert_eq!(answers(&server.snapshot), vec!["puppy", "rat", "fish"]);
+
+        // An added card existing on the server is kept as it is.
+        let fourth = Snapshot {
+            cards: vec![card(4, "shark")],
+            ..Snapshot::default()
+        };
+        server.apply(fourth.changes_since(&Snapshot::default()), &config());
+        assert_eq!(answers(&server.snapshot), vec!["puppy", "rat", "fish"]);
+    }
+
+    #[test]
+    fn test_apply_schedule() {
+        let time = |day, hour| Utc.ymd(2019, 3, day).and_hms(hour, 0, 0);
+        let base = Snapshot {
+            cards: vec![card(1, "dog"), card(2, "cat")],
+            schedule: vec![
+                item(1, "2019-03-10T08:00:00Z"),
+                item(2, "2019-03-10T08:00:00Z"),
+            ],
+            ..Snapshot::default()
+        };
+        let mut server = Server {
+            snapshot: Snapshot {
+                cards: base.cards.clone(),
+                schedule: base.schedule.clone(),
+                ..Snapshot::default()
+            },
+            ..Server::default()
+        };
+
+        // Both clients review card 1, the first one also card 2.
+        let mut first = Snapshot {
+            cards: base.cards.clone(),
+            schedule: vec![
+                item(1, "2019-03-12T08:00:00Z"),
+                item(2, "2019-03-12T08:00:00Z"),
+            ],
+            reviews: vec![
+                Review::new(1, time(12, 8), 5),
+                Review::new(2, time(12, 8), 5),
+            ],
+            ..Snapshot::default()
+        };
+        first.schedule[1].1 =
+            ScheduleItem::replay(Some(&base.schedule[1].1), 2, &first.reviews, &config()).unwrap();
+        server.apply(first.changes_since(&base), &config());
+        assert_eq!(server.snapshot.schedule[1], first.schedule[1]);
+
+        let second = Snapshot {
+            cards: base.cards.clone(),
+            schedule: vec![
+                item(1, "2019-03-13T08:00:00Z"),
+                item(2, "2019-03-10T08:00:00Z"),
+            ],
+            reviews: vec![Review::new(1, time(13, 8), 1)],
+            ..Snapshot::default()
+        };
+        server.apply(second.changes_since(&base), &config());
+        let reviews = vec![
+            Review::new(1, time(12, 8), 5),
+            Review::new(1, time(13, 8), 1),
+        ];
+        let replayed =
+            ScheduleItem::replay(Some(&base.schedule[0].1), 1, &reviews, &config()).unwrap();
+        assert_eq!(server.snapshot.schedule[0], (1, replayed));
+        assert_eq!(server.snapshot.schedule[1], first.schedule[1]);
+        assert_eq!(server.snapshot.reviews.len(), 3);
+
+        // Schedule of a deleted card is removed.
+        let third = Snapshot {
+            cards: vec![card(1, "dog")],
+            schedule: vec![item(1, "2019-03-13T08:00:00Z")],
+            ..Snapshot::default()
+        };
+        server.apply(third.changes_since(&base), &config());
+        let ids: Vec<u64> = server.snapshot.schedule.iter().map(|&(id, _)| id).collect();
+        assert_eq!(ids, vec![1]);
+    }
+}
@@ -1,10 +1,53 @@
 use assert_cmd::prelude::*;
-use dirs;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use regex::Regex;
 use std::fs;
-use std::process::Command;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Creates a new empty directory to be used as home directory of tested
+/// commands.
+fn temp_home() -> PathBuf {
+    let name: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+    let mut path = std::env::temp_dir();
+    path.push(format!("vole-test-{}", name));
+    fs::create_dir(&path).unwrap();
+    path
+}
+
+/// Runs vole with `args` in home directory `home`, feeds it with `input` and
+/// returns its standard output.
+fn run(home: &PathBuf, args: &[&str], input: &str) -> String {
+    let mut child = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", home)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stderr.len(), 0);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Replaces all card and note IDs in `content` with `ID`. New IDs are partly
+/// random.
+fn mask_ids(content: &str) -> String {
+    let re = Regex::new(r"\b[0-9a-f]{16}\b").unwrap();
+    re.replace_all(content, "ID").to_string()
+}
 
 #[test]
 fn test_add() {
@@ -66,16 +109,20 @@ fn test_biadd() {
     let re = Regex::new(r"^[a-z0-9]{16}$").unwrap();
 
     let parts: Vec<&str> = lines[0].split("\t").collect();
-    assert_eq!(parts.len(), 3);
+    assert_eq!(parts.len(), 4);
     assert!(re.is_match(parts[0]));
     assert_eq!(parts[1], second);
     assert_eq!(parts[2], first);
+    assert!(parts[3].starts_with("note="));
+    assert!(parts[3].ends_with(":1"));
+    let note = &parts[3][..parts[3].len() - 2];
 
     let parts: Vec<&str> = lines[1].split("\t").collect();
-    assert_eq!(parts.len(), 3);
+    assert_eq!(parts.len(), 4);
     assert!(re.is_match(parts[0]));
     assert_eq!(parts[1], first);
     assert_eq!(parts[2], second);
+    assert_eq!(parts[3], format!("{}:0", note));
 }
 
 #[test]
@@ -131,3 +178,1032 @@ fn test_find() {
     assert_eq!(parts[1], "and this will also be");
     assert_eq!(parts[2], "řč čeština matched");
 }
+
+#[test]
+fn test_learn_now() {
+    let home = temp_home();
+    run(&home, &["add", "hello", "ahoj"], "");
+    let config = "timezone = UTC\nlearning_steps = 1m 10m\nlearn_ahead = 0m\n";
+    fs::write(home.join(".vole").join("config.txt"), config).unwrap();
+    let schedule_path = home.join(".vole").join("schedule.txt");
+
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-10T08:00:00Z"],
+        "y\n5\ny\n",
+    );
+    assert!(output.contains("Q: hello"));
+    assert!(output.contains("A: ahoj"));
+    assert!(output.contains("the next one at 08:10."));
+
+    let schedule = fs::read_to_string(&schedule_path).unwrap();
+    let parts: Vec<&str> = schedule.trim().split("\t").collect();
+    assert_eq!(parts[1], "2019-03-10T08:10:00Z");
+    assert_eq!(parts[5], "learning:1");
+
+    let output = run(&home, &["learn", "--now", "2019-03-10T08:05:00Z"], "");
+    assert!(!output.contains("Q: hello"));
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-10T08:10:00Z"],
+        "y\n5\ny\n",
+    );
+    assert!(output.contains("Q: hello"));
+    assert!(output.contains("This is it for today!"));
+
+    let schedule = fs::read_to_string(&schedule_path).unwrap();
+    let parts: Vec<&str> = schedule.trim().split("\t").collect();
+    assert_eq!(parts[1], "2019-03-11T04:00:00Z");
+    assert_eq!(parts[2], "2019-03-10T08:10:00Z");
+    assert_eq!(parts[5], "review");
+
+    let output = run(&home, &["learn", "--now", "2019-03-11T03:00:00Z"], "");
+    assert!(output.contains("This is it for today!"));
+    let output = run(&home, &["learn", "--now", "2019-03-11T04:00:00Z"], "q\n");
+    assert!(output.contains("Q: hello"));
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_bury_siblings() {
+    let home = temp_home();
+    run(&home, &["add", "-b", "first", "second"], "");
+    let config = "timezone = UTC\nlearning_steps =\nbury_siblings = true\n";
+    fs::write(home.join(".vole").join("config.txt"), config).unwrap();
+
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-10T08:00:00Z"],
+        "y\n5\ny\n",
+    );
+    assert!(output.contains("Q: first"));
+    assert!(!output.contains("Q: second"));
+    assert!(output.contains("This is it for today!"));
+
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-11T08:00:00Z"],
+        "y\n5\ny\n",
+    );
+    assert!(output.contains("Q: second"));
+    assert!(!output.contains("Q: first"));
+
+    let output = run(&home, &["learn", "--now", "2019-03-11T09:00:00Z"], "");
+    assert!(output.contains("This is it for today!"));
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_notes() {
+    let home = temp_home();
+    let types = "[vocabulary]\n\
+                 fields = word translation plural\n\
+                 question = {{word}}\n\
+                 answer = {{translation}}, pl. {{plural}}\n\
+                 question = {{translation}}\n\
+                 answer = {{word}}\n";
+    run(&home, &["add", "plain", "card"], "");
+    fs::write(home.join(".vole").join("types.txt"), types).unwrap();
+    run(
+        &home,
+        &["add-note", "vocabulary", "word=Hund", "translation=dog"],
+        "",
+    );
+
+    let notes_path = home.join(".vole").join("notes.txt");
+    let notes = fs::read_to_string(&notes_path).unwrap();
+    let note_id = &notes[..16];
+
+    let cards_path = home.join(".vole").join("cards.txt");
+    let cards = fs::read_to_string(&cards_path).unwrap();
+    let lines: Vec<&str> = cards.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "#vole-cards 2");
+    assert!(lines[2].ends_with(&format!("\tHund\tdog, pl. \tnote={}:0", note_id)));
+    assert!(lines[3].ends_with(&format!("\tdog\tHund\tnote={}:1", note_id)));
+
+    run(&home, &["edit-note", note_id, "plural=Hunde"], "");
+    let edited = fs::read_to_string(&cards_path).unwrap();
+    let edited: Vec<&str> = edited.lines().collect();
+    assert_eq!(edited.len(), 4);
+    assert_eq!(edited[..2], lines[..2]);
+    assert_eq!(
+        edited[2],
+        format!(
+            "{}\tHund\tdog, pl. Hunde\tnote={}:0",
+            &lines[2][..16],
+            note_id
+        )
+    );
+
+    let notes = fs::read_to_string(&notes_path).unwrap();
+    assert_eq!(
+        notes,
+        format!(
+            "{}\tvocabulary\tword=Hund\ttranslation=dog\tplural=Hunde\n",
+            note_id
+        )
+    );
+
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["add-note", "vocabulary", "gender=m"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Note type \"vocabulary\" has no field \"gender\".\n"
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_cloze() {
+    let home = temp_home();
+    run(
+        &home,
+        &["add-note", "cloze", "text=Ich {{c1::habe}} {{c2::Hunger}}."],
+        "",
+    );
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-10T12:00:00Z"],
+        "y\n5\nq\n",
+    );
+    assert!(output.starts_with("Q: Ich [...] Hunger.\n"));
+    assert!(output.contains("A: Ich habe Hunger.\n"));
+
+    let notes = fs::read_to_string(home.join(".vole").join("notes.txt")).unwrap();
+    run(
+        &home,
+        &[
+            "edit-note",
+            &notes[..16],
+            "text=Ich {{c1::hatte}} {{c2::Hunger}}.",
+        ],
+        "",
+    );
+    let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+    assert_eq!(
+        mask_ids(&cards),
+        "#vole-cards 2\n\
+         ID\tIch [...] Hunger.\tIch hatte Hunger.\tnote=ID:0\n\
+         ID\tIch hatte [...].\tIch hatte Hunger.\tnote=ID:1\n"
+    );
+
+    // The card of the removed deletion is removed with its schedule.
+    run(
+        &home,
+        &["edit-note", &notes[..16], "text=Ich {{c1::hatte}} Hunger."],
+        "",
+    );
+    let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+    assert_eq!(
+        mask_ids(&cards),
+        "#vole-cards 2\nID\tIch [...] Hunger.\tIch hatte Hunger.\tnote=ID:0\n"
+    );
+    let schedule = fs::read_to_string(home.join(".vole").join("schedule.txt")).unwrap();
+    assert_eq!(schedule.lines().count(), 2);
+    assert_eq!(run(&home, &["check"], ""), "No problems found.\n");
+
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["edit-note", &notes[..16], "text=Ich\t{{c1::hatte}}."])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Field \"text\" can't contain TAB or line break characters.\n"
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_alternatives() {
+    let home = temp_home();
+    run(
+        &home,
+        &[
+            "add", "Hund", "dog", "--also", "hound", "--also", "canine", "--hint", "animal",
+        ],
+        "",
+    );
+
+    let output = run(&home, &["find", "^can"], "");
+    assert_eq!(
+        mask_ids(&output),
+        "ID\tHund\tdog\talternatives=hound|canine\thint=animal\n"
+    );
+
+    let config = "timezone = UTC\ntype_answer = yes\n";
+    fs::write(home.join(".vole").join("config.txt"), config).unwrap();
+    let output = run(
+        &home,
+        &["learn", "--now", "2019-03-10T12:00:00Z"],
+        "Hound\n5\nq\n",
+    );
+    assert!(
+        output.starts_with("Q: Hund\nH: animal\nYour answer: Correct!\nA: dog; hound; canine\n")
+    );
+
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["add", "Katze", "cat", "--also", " "])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Alternative answer can't be empty.\n"
+    );
+
+    run(
+        &home,
+        &["add", "Katze", "cat", "--hint", "pet\tor\nanimal"],
+        "",
+    );
+    let output = run(&home, &["find", "^cat$"], "");
+    assert_eq!(
+        mask_ids(&output),
+        "ID\tKatze\tcat\thint=pet\\tor\\nanimal\n"
+    );
+    assert_eq!(run(&home, &["check"], ""), "No problems found.\n");
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_markup() {
+    let home = temp_home();
+    run(&home, &["add", "**der** Hund", "dog\\n- *pl.* dogs"], "");
+    let output = run(&home, &["learn"], "y\n5\nq\n");
+    assert!(output.starts_with("Q: der Hund\n"));
+    assert!(output.contains("A: dog\n   - pl. dogs\n"));
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_media() {
+    let home = temp_home();
+    let audio_path = home.join("hund.mp3");
+    fs::write(&audio_path, "woof").unwrap();
+    let image_path = home.join("dog.png");
+    fs::write(&image_path, "dog").unwrap();
+
+    run(
+        &home,
+        &[
+            "add",
+            "Hund",
+            "dog",
+            "--audio",
+            audio_path.to_str().unwrap(),
+            "--answer-image",
+            image_path.to_str().unwrap(),
+        ],
+        "",
+    );
+    fs::write(&audio_path, "bark").unwrap();
+    run(
+        &home,
+        &[
+            "add",
+            "Hunde",
+            "dogs",
+            "--audio",
+            audio_path.to_str().unwrap(),
+        ],
+        "",
+    );
+
+    let media_dir = home.join(".vole").join("media");
+    assert_eq!(
+        fs::read_to_string(media_dir.join("hund.mp3")).unwrap(),
+        "woof"
+    );
+    assert_eq!(
+        fs::read_to_string(media_dir.join("hund-1.mp3")).unwrap(),
+        "bark"
+    );
+    let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+    assert_eq!(
+        mask_ids(&cards),
+        "#vole-cards 2\n\
+         ID\tHund\tdog\taudio=hund.mp3\tanswer_image=dog.png\n\
+         ID\tHunde\tdogs\taudio=hund-1.mp3\n"
+    );
+
+    let played = home.join("played.mp3");
+    let config = format!("audio_command = cp {{file}} {}\n", played.to_str().unwrap());
+    fs::write(home.join(".vole").join("config.txt"), config).unwrap();
+    let output = run(&home, &["learn"], "y\n5\nq\n");
+    assert!(output.contains("A: dog\nImage: dog.png\n"));
+    assert_eq!(fs::read_to_string(&played).unwrap(), "woof");
+
+    let odd_path = home.join("a|b.mp3");
+    fs::write(&odd_path, "meow").unwrap();
+    run(
+        &home,
+        &["add", "Katze", "cat", "--audio", odd_path.to_str().unwrap()],
+        "",
+    );
+    let output = run(&home, &["find", "^cat$"], "");
+    assert_eq!(mask_ids(&output), "ID\tKatze\tcat\taudio=a\\|b.mp3\n");
+    assert_eq!(
+        fs::read_to_string(media_dir.join("a|b.mp3")).unwrap(),
+        "meow"
+    );
+
+    // Media of merged answers are attached to the existing card.
+    run(
+        &home,
+        &[
+            "add",
+            "Katze",
+            "kitty",
+            "--duplicate",
+            "merge",
+            "--audio",
+            odd_path.to_str().unwrap(),
+            "--image",
+            image_path.to_str().unwrap(),
+        ],
+        "",
+    );
+    let output = run(&home, &["find", "^cat$"], "");
+    assert_eq!(
+        mask_ids(&output),
+        "ID\tKatze\tcat\talternatives=kitty\taudio=a\\|b.mp3\timage=dog.png\n"
+    );
+    assert_eq!(run(&home, &["check"], ""), "No problems found.\n");
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_speak() {
+    let home = temp_home();
+    run(&home, &["add", "Hund", "dog"], "");
+    let spoken = home.join("spoken");
+    fs::create_dir(&spoken).unwrap();
+    let config = format!(
+        "speak_command = touch {}/{{lang}}-{{text}}\nquestion_language = de\n",
+        spoken.to_str().unwrap()
+    );
+    fs::write(home.join(".vole").join("config.txt"), config).unwrap();
+
+    let output = run(&home, &["learn"], "s\ny\n5\nq\n");
+    assert!(output.contains("Show answer [y, s, q, ?]? Show answer [y, s, q, ?]? A: dog"));
+    assert!(output.contains("Continue with another card [y, q, ?]? "));
+    assert!(spoken.join("de-Hund").exists());
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_migrate() {
+    let home = temp_home();
+    let vole_dir = home.join(".vole");
+    run(&home, &["add", "Hund", "dog", "--also", "hound"], "");
+    run(&home, &["add", "-b", "Katze", "cat"], "");
+    run(
+        &home,
+        &["learn", "--now", "2019-03-10T12:00:00Z"],
+        "y\n5\nq\n",
+    );
+    let cards = fs::read_to_string(vole_dir.join("cards.txt")).unwrap();
+    let mut schedule: Vec<String> = fs::read_to_string(vole_dir.join("schedule.txt"))
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    schedule.sort();
+    let progress = fs::read_to_string(vole_dir.join("progress.txt")).unwrap();
+
+    run(&home, &["migrate", "--to", "sqlite"], "");
+    assert!(vole_dir.join("vole.sqlite").exists());
+    assert!(!vole_dir.join("cards.txt").exists());
+
+    run(&home, &["add", "Maus", "mouse"], "");
+    let output = run(&home, &["find", "^M"], "");
+    assert_eq!(mask_ids(&output), "ID\tMaus\tmouse\n");
+
+    run(&home, &["migrate", "--to", "text"], "");
+    assert!(!vole_dir.join("vole.sqlite").exists());
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("cards.txt")).unwrap(),
+        format!("{}{}", cards, output)
+    );
+    let mut migrated: Vec<String> = fs::read_to_string(vole_dir.join("schedule.txt"))
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    migrated.sort();
+    assert_eq!(migrated, schedule);
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("progress.txt")).unwrap(),
+        progress
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "sqlite"))]
+fn test_migrate() {
+    let home = temp_home();
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["migrate", "--to", "sqlite"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("feature \"sqlite\""));
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_upgrade() {
+    let home = temp_home();
+    let vole_dir = home.join(".vole");
+    fs::create_dir(&vole_dir).unwrap();
+    fs::write(
+        vole_dir.join("cards.txt"),
+        "0000000000000002\tKatze\tcat\n0000000000000001\tHund\tdog\n",
+    )
+    .unwrap();
+    fs::write(
+        vole_dir.join("schedule.txt"),
+        "0000000000000001\t2019-03-12\t2019-03-10\t1\t2.5\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        run(&home, &["migrate"], ""),
+        "Upgraded cards.txt from format version 1.\n\
+         Upgraded schedule.txt from format version 1.\n"
+    );
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("cards.txt")).unwrap(),
+        "#vole-cards 2\n0000000000000001\tHund\tdog\n0000000000000002\tKatze\tcat\n"
+    );
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("schedule.txt")).unwrap(),
+        "#vole-schedule 2\n\
+         0000000000000001\t2019-03-12T04:00:00Z\t2019-03-10T04:00:00Z\t1\t2.5\treview\n"
+    );
+    assert!(!run(&home, &["backup", "list"], "").is_empty());
+    assert_eq!(run(&home, &["migrate"], ""), "All files are up to date.\n");
+
+    fs::write(vole_dir.join("cards.txt"), "#vole-cards 3\n").unwrap();
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["find", "Hund"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("cards.txt is of format version 3"));
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_sync() {
+    let server_home = temp_home();
+    let mut server = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &server_home)
+        .args(["serve-sync", "--address", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The reader is kept open so that the server can print more lines.
+    let mut output = BufReader::new(server.stdout.take().unwrap());
+    let mut listening = String::new();
+    output.read_line(&mut listening).unwrap();
+    let address = listening
+        .trim()
+        .trim_start_matches("Listening on ")
+        .trim_end_matches('.')
+        .to_string();
+
+    let laptop = temp_home();
+    let desktop = temp_home();
+    run(&laptop, &["add", "Hund", "dog"], "");
+    run(&desktop, &["add", "Katze", "cat"], "");
+    run(&laptop, &["sync", &address], "");
+    run(&desktop, &["sync", &address], "");
+    run(
+        &desktop,
+        &["learn", "--now", "2019-03-10T12:00:00Z"],
+        "y\n5\ny\ny\n5\nq\n",
+    );
+    run(&desktop, &["sync", &address], "");
+    run(&laptop, &["sync", &address], "");
+
+    for home in &[&laptop, &desktop] {
+        let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+        assert_eq!(
+            mask_ids(&cards),
+            "#vole-cards 2\nID\tHund\tdog\nID\tKatze\tcat\n"
+        );
+        let schedule = fs::read_to_string(home.join(".vole").join("schedule.txt")).unwrap();
+        assert_eq!(schedule.lines().count(), 3);
+        let reviews = fs::read_to_string(home.join(".vole").join("reviews.txt")).unwrap();
+        assert_eq!(reviews.lines().count(), 3);
+    }
+
+    // A note edited on one device isn't overwritten by a device which
+    // didn't change it.
+    run(
+        &laptop,
+        &["add-note", "cloze", "text=Ich {{c1::habe}} Hunger."],
+        "",
+    );
+    run(&laptop, &["sync", &address], "");
+    run(&desktop, &["sync", &address], "");
+    let notes = fs::read_to_string(laptop.join(".vole").join("notes.txt")).unwrap();
+    run(
+        &laptop,
+        &["edit-note", &notes[..16], "text=Ich {{c1::hatte}} Hunger."],
+        "",
+    );
+    run(&laptop, &["sync", &address], "");
+    run(&desktop, &["sync", &address], "");
+    run(&laptop, &["sync", &address], "");
+    for home in &[&laptop, &desktop] {
+        let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+        assert!(cards.contains("\tIch hatte Hunger.\t"));
+        assert!(!cards.contains("habe"));
+    }
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    for home in [server_home, laptop, desktop] {
+        fs::remove_dir_all(home).unwrap();
+    }
+}
+
+#[test]
+fn test_merge_driver() {
+    let home = temp_home();
+    let base = home.join("base");
+    let ours = home.join("ours");
+    let theirs = home.join("theirs");
+    let merge = |name: &str| {
+        Command::cargo_bin("vole")
+            .unwrap()
+            .env("HOME", &home)
+            .arg("merge-driver")
+            .args([&base, &ours, &theirs])
+            .arg(name)
+            .output()
+            .unwrap()
+    };
+
+    fs::write(&base, "0000000000000001\tHund\tdog\n").unwrap();
+    fs::write(
+        &ours,
+        "0000000000000001\tHund\thound\n0000000000000003\tMaus\tmouse\n",
+    )
+    .unwrap();
+    fs::write(
+        &theirs,
+        "0000000000000002\tKatze\tcat\n0000000000000001\tHund\tdog\n",
+    )
+    .unwrap();
+    let output = merge("cards.txt");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&ours).unwrap(),
+        "#vole-cards 2\n\
+         0000000000000001\tHund\thound\n\
+         0000000000000002\tKatze\tcat\n\
+         0000000000000003\tMaus\tmouse\n"
+    );
+
+    fs::write(&theirs, "0000000000000001\tHund\tcanine\n").unwrap();
+    let output = merge("cards.txt");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Conflicting changes of cards.txt, our version kept: 0000000000000001\n"
+    );
+
+    let item = |revisit: &str| {
+        format!(
+            "0000000000000001\t2019-03-12T04:00:00Z\t{}\t1\t2.5\treview\n",
+            revisit
+        )
+    };
+    fs::write(&base, item("2019-03-09T08:00:00Z")).unwrap();
+    fs::write(
+        &ours,
+        format!("#vole-schedule 2\n{}", item("2019-03-10T08:00:00Z")),
+    )
+    .unwrap();
+    fs::write(&theirs, item("2019-03-11T08:00:00Z")).unwrap();
+    let output = merge("schedule.txt");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&ours).unwrap(),
+        format!("#vole-schedule 2\n{}", item("2019-03-11T08:00:00Z"))
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_lock() {
+    let home = temp_home();
+    run(&home, &["add", "Hund", "dog"], "");
+
+    let mut session = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["learn", "--now", "2019-03-10T12:00:00Z"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut output = BufReader::new(session.stdout.take().unwrap());
+    let mut question = String::new();
+    output.read_line(&mut question).unwrap();
+    assert_eq!(question, "Q: Hund\n");
+
+    let second = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .arg("learn")
+        .output()
+        .unwrap();
+    assert!(!second.status.success());
+    assert!(String::from_utf8(second.stderr)
+        .unwrap()
+        .starts_with("Another learning session is running."));
+    let read_only = run(&home, &["learn", "--read-only"], "y\n5\nq\n");
+    assert!(read_only.starts_with("Q: Hund\n"));
+
+    run(&home, &["add", "Katze", "cat"], "");
+    session
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"y\n5\nq\n")
+        .unwrap();
+    assert!(session.wait().unwrap().success());
+
+    let cards = fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap();
+    assert_eq!(
+        mask_ids(&cards),
+        "#vole-cards 2\nID\tHund\tdog\nID\tKatze\tcat\n"
+    );
+    let schedule = fs::read_to_string(home.join(".vole").join("schedule.txt")).unwrap();
+    assert_eq!(schedule.lines().count(), 2);
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_check() {
+    let home = temp_home();
+    let vole_dir = home.join(".vole");
+    fs::create_dir(&vole_dir).unwrap();
+    fs::write(
+        vole_dir.join("cards.txt"),
+        "0000000000000001\tHund\tdog\n\
+         0000000000000001\tHund\tdog\n\
+         0000000000000002\tKatze\n",
+    )
+    .unwrap();
+    fs::write(
+        vole_dir.join("schedule.txt"),
+        "0000000000000001\t2019-03-12T04:00:00Z\t2019-04-01T08:00:00Z\t1\t2.5\treview\n\
+         0000000000000003\t2019-03-12T04:00:00Z\t2019-03-10T08:00:00Z\t1\t2.5\treview\n",
+    )
+    .unwrap();
+    let check = |fix: bool| {
+        let mut command = Command::cargo_bin("vole").unwrap();
+        command
+            .env("HOME", &home)
+            .args(["check", "--now", "2019-03-12T12:00:00Z"]);
+        if fix {
+            command.arg("--fix");
+        }
+        command.output().unwrap()
+    };
+
+    let output = check(false);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "cards.txt:2: Duplicate of the card on line 1.\n\
+         cards.txt:3: Expected at least three TAB separated tokens, got: \
+         0000000000000002\tKatze (can't be fixed automatically)\n\
+         schedule.txt:1: Last revisit is in the future: 2019-04-01T08:00:00Z\n\
+         schedule.txt:2: Schedule of missing card 0000000000000003.\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Problems found: 4, fixable with --fix: 3\n"
+    );
+
+    let output = check(true);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with("Fixed problems: 3\n"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Problems left to fix manually: 1\n"
+    );
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("cards.txt")).unwrap(),
+        "#vole-cards 2\n0000000000000001\tHund\tdog\n0000000000000002\tKatze\n"
+    );
+    assert_eq!(
+        fs::read_to_string(vole_dir.join("schedule.txt")).unwrap(),
+        "#vole-schedule 2\n\
+         0000000000000001\t2019-03-12T12:00:00Z\t2019-03-12T12:00:00Z\t1\t2.5\treview\n"
+    );
+
+    fs::write(vole_dir.join("cards.txt"), "0000000000000001\tHund\tdog\n").unwrap();
+    assert_eq!(
+        run(&home, &["check", "--now", "2019-03-12T12:00:00Z"], ""),
+        "No problems found.\n"
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_backup_once() {
+    let home = temp_home();
+    run(
+        &home,
+        &["add-note", "cloze", "text=Ich {{c1::habe}} Hunger."],
+        "",
+    );
+    let notes = fs::read_to_string(home.join(".vole").join("notes.txt")).unwrap();
+    run(
+        &home,
+        &[
+            "edit-note",
+            &notes[..16],
+            "text=Ich {{c1::habe}} {{c2::Hunger}}.",
+        ],
+        "",
+    );
+
+    // Notes and cards are rewritten but backed up only once.
+    let backups = run(&home, &["backup", "list"], "");
+    assert_eq!(backups.lines().count(), 1);
+    let backup = home.join(".vole").join("backups").join(backups.trim_end());
+    let cards = fs::read_to_string(backup.join("cards.txt")).unwrap();
+    assert_eq!(cards.lines().count(), 2);
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_backup() {
+    let home = temp_home();
+    run(&home, &["add", "Hund", "dog"], "");
+    assert_eq!(run(&home, &["backup", "list"], ""), "");
+
+    run(
+        &home,
+        &["learn", "--now", "2019-03-10T12:00:00Z"],
+        "y\n5\nq\n",
+    );
+    let schedule_path = home.join(".vole").join("schedule.txt");
+    assert_eq!(
+        fs::read_to_string(&schedule_path).unwrap().lines().count(),
+        2
+    );
+
+    let backups = run(&home, &["backup", "list"], "");
+    let backups: Vec<&str> = backups.lines().collect();
+    assert_eq!(backups.len(), 1);
+    run(&home, &["backup", "restore", backups[0]], "");
+    assert_eq!(fs::read_to_string(&schedule_path).unwrap(), "");
+    assert_eq!(
+        mask_ids(&fs::read_to_string(home.join(".vole").join("cards.txt")).unwrap()),
+        "#vole-cards 2\nID\tHund\tdog\n"
+    );
+
+    // The restore itself can be undone.
+    let backups = run(&home, &["backup", "list"], "");
+    let backups: Vec<&str> = backups.lines().collect();
+    assert_eq!(backups.len(), 2);
+    run(&home, &["backup", "restore", backups[0]], "");
+    assert_eq!(
+        fs::read_to_string(&schedule_path).unwrap().lines().count(),
+        2
+    );
+
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["backup", "restore", "yesterday"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "There is no backup yesterday.\n"
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_duplicates() {
+    let home = temp_home();
+    let cards_path = home.join(".vole").join("cards.txt");
+    run(&home, &["add", "der Hund", "dog"], "");
+
+    let output = Command::cargo_bin("vole")
+        .unwrap()
+        .env("HOME", &home)
+        .args(["add", "Der  HUND", "hound"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        mask_ids(&String::from_utf8(output.stderr).unwrap()),
+        "Card ID asks the same question: der Hund\n\
+         Use --duplicate to skip, merge or add the card.\n"
+    );
+
+    let output = run(
+        &home,
+        &["add", "der Hund", "dog", "--duplicate", "skip"],
+        "",
+    );
+    assert_eq!(
+        mask_ids(&output),
+        "Skipped, card ID asks the same question.\n"
+    );
+    run(
+        &home,
+        &[
+            "add",
+            "der Hund",
+            "Dog",
+            "--also",
+            "hound",
+            "--duplicate",
+            "merge",
+        ],
+        "",
+    );
+    run(&home, &["add", "der Hunde", "dogs"], "");
+    assert_eq!(
+        mask_ids(&fs::read_to_string(&cards_path).unwrap()),
+        "#vole-cards 2\n\
+         ID\tder Hund\tdog\talternatives=hound\n\
+         ID\tder Hunde\tdogs\n"
+    );
+
+    assert_eq!(run(&home, &["dedupe"], ""), "No duplicates found.\n");
+    run(
+        &home,
+        &["add", "der Hund", "canine", "--duplicate", "add"],
+        "",
+    );
+    run(&home, &["add", "die Katze", "cat"], "");
+    assert_eq!(
+        mask_ids(&run(&home, &["dedupe", "--fuzzy"], "")),
+        "ID\tder Hund\tdog\talternatives=hound\n\
+         ID\tder Hunde\tdogs\n\
+         ID\tder Hund\tcanine\n"
+    );
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn test_add_in_editor() {
+    let home = temp_home();
+    let vole_dir = home.join(".vole");
+    let cards_path = vole_dir.join("cards.txt");
+    let draft_path = home.join("draft.txt");
+    run(&home, &["add", "der Hund", "dog"], "");
+
+    // The "editor" replaces the draft with a prepared file.
+    let add = |args: &[&str]| {
+        Command::cargo_bin("vole")
+            .unwrap()
+            .env("HOME", &home)
+            .env_remove("VISUAL")
+            .env("EDITOR", format!("cp {}", draft_path.display()))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    fs::write(&draft_path, "# Comment\ndie Katze\ncat\n- *pl.* cats\n").unwrap();
+    let output = add(&["add"]);
+    assert!(output.status.success());
+    assert!(!vole_dir.join("add.txt").exists());
+
+    fs::write(
+        &draft_path,
+        "die Maus\nmouse\n\nder HUND\nhound\n\nder Vogel\n",
+    )
+    .unwrap();
+    let output = add(&["add", "--batch"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Error on line 7: The card has no answer.\n"));
+    assert!(stderr.ends_with(" and opened again by the next vole add --batch.\n"));
+    assert!(vole_dir.join("add-batch.txt").exists());
+    assert!(!vole_dir.join("add.txt").exists());
+
+    // A single card draft doesn't accept more cards.
+    let output = add(&["add"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(
+        "Error on line 4: Only one card can be added, use vole add --batch to add more.\n"
+    ));
+    assert!(stderr.ends_with(" and opened again by the next vole add.\n"));
+    assert!(vole_dir.join("add.txt").exists());
+    fs::remove_file(vole_dir.join("add.txt")).unwrap();
+
+    fs::write(
+        &draft_path,
+        "die Maus\nmouse\n\nder HUND\nhound\n\nder Vogel\nbird\n",
+    )
+    .unwrap();
+    let output = add(&["add", "--batch"]);
+    assert!(!output.status.success());
+    let stderr = mask_ids(&String::from_utf8(output.stderr).unwrap());
+    assert!(stderr.starts_with(
+        "Card ID asks the same question: der Hund\n\
+         Use --duplicate to skip, merge or add the card.\n\
+         The cards are kept in "
+    ));
+    assert!(vole_dir.join("add-batch.txt").exists());
+
+    let output = add(&["add", "--batch", "--duplicate", "skip"]);
+    assert!(output.status.success());
+    assert_eq!(
+        mask_ids(&String::from_utf8(output.stdout).unwrap()),
+        "Skipped, card ID asks the same question.\n"
+    );
+    assert!(!vole_dir.join("add-batch.txt").exists());
+    assert_eq!(
+        mask_ids(&fs::read_to_string(&cards_path).unwrap()),
+        "#vole-cards 2\n\
+         ID\tder Hund\tdog\n\
+         ID\tdie Katze\tcat\\n- *pl.* cats\n\
+         ID\tdie Maus\tmouse\n\
+         ID\tder Vogel\tbird\n"
+    );
+
+    fs::write(&draft_path, "# Nothing.\n").unwrap();
+    assert_eq!(
+        String::from_utf8(add(&["add"]).stdout).unwrap(),
+        "No cards added.\n"
+    );
+
+    // Notes of bidirectional cards are stored together.
+    fs::write(&draft_path, "der Fisch\nfish\n\ndie Kuh\ncow\n").unwrap();
+    assert!(add(&["add", "--batch", "--bidirectional"]).status.success());
+    let notes = fs::read_to_string(vole_dir.join("notes.txt")).unwrap();
+    assert_eq!(notes.lines().count(), 2);
+    let cards = fs::read_to_string(&cards_path).unwrap();
+    assert_eq!(cards.lines().count(), 9);
+
+    // Cards of notes added before are reported by their IDs.
+    fs::write(&draft_path, "die Ente\nduck\n\nduck\ndie Ente\n").unwrap();
+    let output = add(&["add", "--batch", "--bidirectional"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let id = &stderr[5..21];
+    assert_ne!(id, "0000000000000000");
+    assert!(stderr.starts_with(&format!("Card {} asks the same question: duck\n", id)));
+    assert!(!fs::read_to_string(&cards_path).unwrap().contains(id));
+    fs::remove_file(vole_dir.join("add-batch.txt")).unwrap();
+
+    fs::remove_dir_all(home).unwrap();
+}